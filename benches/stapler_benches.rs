@@ -4,7 +4,7 @@ use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion
 use merge::{
     loader::fs::{FileSystemMergingDestination, FileSystemMergingSource},
     tests::create_sample_pdf,
-    FileSystemOptions,
+    StaplerOptions,
 };
 use stapler::{
     merge::{self, tests::COMPRESS_OUTPUT_WHEN_TESTING},
@@ -27,15 +27,16 @@ fn stapler_benchmark(c: &mut Criterion) {
     // ensure the testfiles directory exists
     fs::create_dir_all(testfiles_dir.clone()).unwrap();
 
-    let file_options = FileSystemOptions {
+    let file_options = StaplerOptions {
         input_sources: input_files
             .iter()
-            .map(|input_file| FileSystemMergingSource { input_file })
+            .map(|input_file| FileSystemMergingSource::new(input_file))
             .collect(),
         destination: FileSystemMergingDestination {
-            output_file: &output_file,
+            output_file: output_file.clone(),
         },
-        compress: COMPRESS_OUTPUT_WHEN_TESTING,
+        compression: COMPRESS_OUTPUT_WHEN_TESTING,
+        ..Default::default()
     };
 
     c.bench_function(&format!("stapler running on {} files", max_files), |b| {
@@ -47,7 +48,7 @@ fn stapler_benchmark(c: &mut Criterion) {
                     .enumerate()
                     .for_each(|(index, source)| {
                         create_sample_pdf(&(index + 1).to_string())
-                            .save(source.input_file)
+                            .save(&source.input_file)
                             .unwrap();
                     });
                 black_box(file_options.clone())
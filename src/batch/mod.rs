@@ -0,0 +1,191 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::manifest::{
+    deserialize, resolve_path, Manifest, ManifestFormat, ManifestInput, ManifestOutput,
+};
+use crate::merge::{metadata::DocumentMetadata, StaplerOptions};
+use crate::report::{BatchReport, JobReport};
+use crate::{stapler, with_thread_pool};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchManifest {
+    jobs: Vec<Manifest>,
+}
+
+/// A set of independent merge jobs, each described like a single `--manifest`.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pub base_dir: PathBuf,
+    pub jobs: Vec<Manifest>,
+    /// Number of threads shared by all jobs, `None` uses one per CPU.
+    pub threads: Option<usize>,
+}
+
+fn job_from_inputs(output_file: String, input_files: Vec<String>) -> Manifest {
+    Manifest {
+        output: ManifestOutput {
+            file: output_file,
+            ..Default::default()
+        },
+        metadata: DocumentMetadata::default(),
+        inputs: input_files
+            .into_iter()
+            .map(|file| ManifestInput {
+                file,
+                ..Default::default()
+            })
+            .collect(),
+    }
+}
+
+impl Batch {
+    /// Parses CSV rows of `output,input`. Rows sharing an output file form one job,
+    /// inputs keep their row order. A leading `output,input` header row is optional.
+    pub fn parse_csv(contents: &str) -> Result<Vec<Manifest>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .comment(Some(b'#'))
+            .from_reader(contents.as_bytes());
+
+        let mut jobs: Vec<(String, Vec<String>)> = Vec::new();
+        for (index, record) in reader.records().enumerate() {
+            let record = record.with_context(|| format!("Invalid CSV row {}", index + 1))?;
+            let line = record.position().map_or(index as u64 + 1, |position| position.line());
+            if index == 0 && record.iter().eq(["output", "input"]) {
+                continue;
+            }
+            anyhow::ensure!(
+                record.len() == 2,
+                "line {}: expected 2 columns (output,input), found {}",
+                line,
+                record.len()
+            );
+            let (output_file, input_file) = (&record[0], &record[1]);
+            anyhow::ensure!(
+                !output_file.is_empty() && !input_file.is_empty(),
+                "line {}: output and input must not be empty",
+                line
+            );
+
+            match jobs.iter_mut().find(|(output, _)| output == output_file) {
+                Some((_, inputs)) => inputs.push(input_file.to_string()),
+                None => jobs.push((output_file.to_string(), vec![input_file.to_string()])),
+            }
+        }
+
+        Ok(jobs
+            .into_iter()
+            .map(|(output_file, input_files)| job_from_inputs(output_file, input_files))
+            .collect())
+    }
+
+    pub fn parse(contents: &str, format: ManifestFormat) -> Result<Vec<Manifest>> {
+        deserialize::<BatchManifest>(contents, format).map(|batch| batch.jobs)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read batch file {}", path.display()))?;
+        let is_csv = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+        let jobs = if is_csv {
+            Batch::parse_csv(&contents)
+        } else {
+            ManifestFormat::from_path(path).and_then(|format| Batch::parse(&contents, format))
+        }
+        .with_context(|| format!("Invalid batch file {}", path.display()))?;
+
+        let batch = Batch {
+            base_dir: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            jobs,
+            threads: None,
+        };
+        batch
+            .validate()
+            .with_context(|| format!("Invalid batch file {}", path.display()))?;
+        Ok(batch)
+    }
+
+    fn validate(&self) -> Result<()> {
+        anyhow::ensure!(!self.jobs.is_empty(), "the batch does not contain any jobs");
+
+        let mut output_files = HashSet::new();
+        for (index, job) in self.jobs.iter().enumerate() {
+            let output_file = resolve_path(&self.base_dir, &job.output.file);
+            anyhow::ensure!(
+                output_files.insert(output_file.clone()),
+                "jobs[{}]: output file {} is written by more than one job",
+                index,
+                output_file.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Runs all jobs in parallel. A failing job never aborts the others, its error is
+    /// recorded in the returned report instead. Jobs load their inputs on the same pool.
+    ///
+    /// `configure` can adjust the options of every job before it runs.
+    pub fn run(self, configure: impl Fn(&mut StaplerOptions) + Sync) -> Result<BatchReport> {
+        let started = Instant::now();
+        let base_dir = self.base_dir;
+        let jobs = with_thread_pool(self.threads, || {
+            self.jobs
+                .into_par_iter()
+                .map(|job| run_job(job, &base_dir, &configure))
+                .collect::<Vec<JobReport>>()
+        })?;
+
+        let succeeded = jobs.iter().filter(|job| job.success).count();
+        Ok(BatchReport {
+            failed: jobs.len() - succeeded,
+            succeeded,
+            jobs,
+            total_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+}
+
+fn run_job(
+    job: Manifest,
+    base_dir: &Path,
+    configure: &(impl Fn(&mut StaplerOptions) + Sync),
+) -> JobReport {
+    let output_file = resolve_path(base_dir, &job.output.file)
+        .to_string_lossy()
+        .to_string();
+    let result = job.into_options(base_dir).and_then(|mut options| {
+        configure(&mut options);
+        stapler(options)
+    });
+    match result {
+        Ok(report) => JobReport {
+            output_file,
+            success: true,
+            error: None,
+            report: Some(report),
+        },
+        Err(error) => JobReport {
+            output_file,
+            success: false,
+            error: Some(format!("{:#}", error)),
+            report: None,
+        },
+    }
+}
@@ -0,0 +1,91 @@
+use std::{env::temp_dir, fs};
+
+use super::*;
+use crate::merge::tests::create_sample_pdf;
+
+#[test]
+fn test_parse_csv_groups_rows_by_output() {
+    let csv = "output,input\nfirst.pdf,a.pdf\nsecond.pdf,c.pdf\nfirst.pdf,b.pdf\n# comment\nsecond.pdf,\"d, final.pdf\"\n";
+
+    let jobs = Batch::parse_csv(csv).unwrap();
+
+    let summary: Vec<(&str, Vec<&str>)> = jobs
+        .iter()
+        .map(|job| {
+            (
+                job.output.file.as_str(),
+                job.inputs.iter().map(|input| input.file.as_str()).collect(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("first.pdf", vec!["a.pdf", "b.pdf"]),
+            ("second.pdf", vec!["c.pdf", "d, final.pdf"]),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_csv_rejects_malformed_rows() {
+    let error = Batch::parse_csv("out.pdf,a.pdf\nout.pdf\n").unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "line 2: expected 2 columns (output,input), found 1"
+    );
+}
+
+#[test]
+fn test_batch_rejects_duplicate_outputs() {
+    let batch = Batch {
+        base_dir: PathBuf::from("jobs"),
+        jobs: Batch::parse_csv("out.pdf,a.pdf\nout.pdf,b.pdf\n./out.pdf,c.pdf\n").unwrap(),
+        threads: None,
+    };
+
+    let error = batch.validate().unwrap_err();
+
+    assert!(
+        error.to_string().starts_with("jobs[1]: output file"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_run_batch_reports_each_job() {
+    let base_dir = temp_dir().join(format!("stapler-batch-test-{}", std::process::id()));
+    fs::create_dir_all(&base_dir).unwrap();
+    for name in ["a", "b", "c"] {
+        create_sample_pdf(name)
+            .save(base_dir.join(format!("{}.pdf", name)))
+            .unwrap();
+    }
+
+    let jobs = Batch::parse_csv(
+        "good.pdf,a.pdf\ngood.pdf,b.pdf\nbad.pdf,c.pdf\nbad.pdf,missing.pdf\n",
+    )
+    .unwrap();
+    let report = Batch {
+        base_dir: base_dir.clone(),
+        jobs,
+        threads: Some(2),
+    }
+    .run(|_| {})
+    .unwrap();
+
+    assert_eq!((report.succeeded, report.failed), (1, 1), "{:?}", report.jobs);
+    assert!(report.has_failures());
+    assert!(report.jobs[0].success);
+    assert_eq!(report.jobs[0].report.as_ref().unwrap().page_count, 2);
+    assert!(!report.jobs[1].success);
+    assert!(report.jobs[1]
+        .error
+        .as_ref()
+        .unwrap()
+        .contains("missing.pdf does not exist"));
+
+    fs::remove_dir_all(base_dir).unwrap();
+}
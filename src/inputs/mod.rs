@@ -0,0 +1,299 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    cmp::Ordering,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use glob::{glob, MatchOptions, Pattern};
+use image::ImageFormat;
+
+/// How far into a file the `%PDF-` header may start. Readers skip leading junk up to
+/// this many bytes, so stapler does too.
+const HEADER_SEARCH_LENGTH: u64 = 1024;
+
+/// Whether the file at `path` starts with a `%PDF-` header.
+pub fn has_pdf_header(path: &Path) -> io::Result<bool> {
+    let mut head = Vec::new();
+    File::open(path)?.take(HEADER_SEARCH_LENGTH).read_to_end(&mut head)?;
+    Ok(head.windows(5).any(|window| window == b"%PDF-"))
+}
+
+/// What an input file holds, which decides how it is loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    Pdf,
+    /// A JPEG, PNG or TIFF image, turned into pages.
+    Image(ImageFormat),
+    /// A `.txt` file, laid out in a monospaced font.
+    Text,
+}
+
+fn is_text_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.to_string_lossy().eq_ignore_ascii_case("txt"))
+}
+
+/// Tells PDFs and supported images apart by their contents and text files by their
+/// `.txt` extension, `None` for anything else.
+pub fn detect_input_kind(path: &Path) -> io::Result<Option<InputKind>> {
+    // Logs may quote a PDF header, so text files go by their name alone.
+    if is_text_path(path) {
+        return Ok(Some(InputKind::Text));
+    }
+    if has_pdf_header(path)? {
+        return Ok(Some(InputKind::Pdf));
+    }
+    let mut head = Vec::new();
+    File::open(path)?.take(16).read_to_end(&mut head)?;
+    Ok(match image::guess_format(&head) {
+        Ok(format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Tiff)) => Some(InputKind::Image(format)),
+        _ => None,
+    })
+}
+
+/// How the files matched by one glob pattern are ordered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Byte-wise by path, so `page10.pdf` comes before `page2.pdf`.
+    #[default]
+    Name,
+    /// By path with runs of digits compared as numbers, ignoring case.
+    Natural,
+    /// Oldest modification first.
+    Modified,
+    /// Oldest creation first, falling back to the modification time where the file
+    /// system does not record creation.
+    Created,
+    /// Smallest file first.
+    Size,
+    /// In the order the glob walk yields them.
+    Given,
+}
+
+impl FromStr for SortOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(order: &str) -> Result<Self> {
+        match order.trim().to_lowercase().as_str() {
+            "name" => Ok(SortOrder::Name),
+            "natural" => Ok(SortOrder::Natural),
+            "mtime" => Ok(SortOrder::Modified),
+            "ctime" => Ok(SortOrder::Created),
+            "size" => Ok(SortOrder::Size),
+            "given" => Ok(SortOrder::Given),
+            other => anyhow::bail!(
+                "'{}' is not a sort order, expected name, natural, mtime, ctime, size or given",
+                other
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputOrder {
+    pub sort: SortOrder,
+    /// Reverse the matches of every pattern after sorting them.
+    pub reverse: bool,
+}
+
+/// Which files directory inputs contribute, and which files are left out of any input.
+#[derive(Debug, Clone, Default)]
+pub struct InputFilter {
+    /// Descend into subdirectories of directory inputs.
+    pub recursive: bool,
+    /// Include hidden files and directories (names starting with a dot) of directory inputs.
+    pub hidden: bool,
+    /// Files to leave out of glob and directory matches, matched against the whole path
+    /// and the file name. `*` stays within one path component, `**` crosses them.
+    pub exclude: Vec<Pattern>,
+}
+
+impl InputFilter {
+    fn excludes(&self, path: &Path) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        self.exclude.iter().any(|pattern| {
+            pattern.matches_path_with(path, options)
+                || path
+                    .file_name()
+                    .is_some_and(|name| pattern.matches_with(&name.to_string_lossy(), options))
+        })
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+/// Collects the PDF, image and text files in `directory`, descending into subdirectories when
+/// `filter.recursive` is set.
+fn walk_directory(directory: &Path, filter: &InputFilter, files: &mut Vec<String>) -> Result<()> {
+    let entries = fs::read_dir(directory)
+        .with_context(|| format!("Cannot read directory {}", directory.display()))?;
+    for entry in entries {
+        let path: PathBuf = entry
+            .with_context(|| format!("Cannot read directory {}", directory.display()))?
+            .path();
+        if (!filter.hidden && is_hidden(&path)) || filter.excludes(&path) {
+            continue;
+        }
+        if path.is_dir() {
+            if filter.recursive {
+                walk_directory(&path, filter, files)?;
+            }
+        } else if path.is_file() && is_input_path(&path) {
+            files.push(path.to_string_lossy().to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Compares two strings with runs of ASCII digits taken as numbers, e.g. `page2` before
+/// `page10`. Other characters compare case-insensitively.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(first_a), Some(first_b)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if first_a.is_ascii_digit() && first_b.is_ascii_digit() {
+            let digits = |text: &str| text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+            let (number_a, rest_a) = a.split_at(digits(a));
+            let (number_b, rest_b) = b.split_at(digits(b));
+            let (trimmed_a, trimmed_b) = (number_a.trim_start_matches('0'), number_b.trim_start_matches('0'));
+            let ordering = trimmed_a
+                .len()
+                .cmp(&trimmed_b.len())
+                .then_with(|| trimmed_a.cmp(trimmed_b))
+                .then_with(|| number_a.len().cmp(&number_b.len()));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            (a, b) = (rest_a, rest_b);
+        } else {
+            let ordering = first_a.to_lowercase().cmp(first_b.to_lowercase());
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            (a, b) = (&a[first_a.len_utf8()..], &b[first_b.len_utf8()..]);
+        }
+    }
+}
+
+fn file_time(path: &str, created: bool) -> SystemTime {
+    fs::metadata(path)
+        .and_then(|metadata| match created {
+            true => metadata.created().or_else(|_| metadata.modified()),
+            false => metadata.modified(),
+        })
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Sorts the matches of one pattern, ties are broken by name.
+pub fn sort_matches(matches: &mut [String], order: InputOrder) {
+    match order.sort {
+        SortOrder::Name => matches.sort(),
+        SortOrder::Natural => matches.sort_by(|a, b| natural_cmp(a, b).then_with(|| a.cmp(b))),
+        SortOrder::Modified | SortOrder::Created => {
+            let created = order.sort == SortOrder::Created;
+            matches.sort_by_cached_key(|path| (file_time(path, created), path.clone()));
+        }
+        SortOrder::Size => matches.sort_by_cached_key(|path| {
+            (fs::metadata(path).map_or(0, |metadata| metadata.len()), path.clone())
+        }),
+        SortOrder::Given => {}
+    }
+    if order.reverse {
+        matches.reverse();
+    }
+}
+
+/// Expands glob patterns and directories into PDF, image and text files. Each pattern's or directory's
+/// matches are ordered on their own by `order`, patterns, directories and literal paths
+/// keep the order they were given in. Returns the files and warnings about patterns and
+/// directories that matched nothing.
+pub fn expand_glob_patterns(
+    patterns: Vec<String>,
+    order: InputOrder,
+    filter: &InputFilter,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let mut expanded_files = Vec::new();
+    let mut warnings = Vec::new();
+
+    for pattern in patterns {
+        if Path::new(&pattern).is_dir() {
+            let mut directory_files = Vec::new();
+            walk_directory(Path::new(&pattern), filter, &mut directory_files)?;
+            if directory_files.is_empty() {
+                warnings.push(format!("No PDF, image or text files found in directory: {}", pattern));
+            } else {
+                sort_matches(&mut directory_files, order);
+                expanded_files.extend(directory_files);
+            }
+        } else if pattern.contains('*') || pattern.contains('?') || pattern.contains('[') {
+            // It's a glob pattern, expand it
+            let glob_matches = glob(&pattern)
+                .with_context(|| format!("Invalid glob pattern: {}", pattern))?;
+
+            let mut pattern_matches = Vec::new();
+            for entry in glob_matches {
+                match entry {
+                    Ok(path) => {
+                        // Only include files that exist and are PDFs, images or text files by extension or contents
+                        if path.is_file() && is_input_path(&path) && !filter.excludes(&path) {
+                            pattern_matches.push(path.to_string_lossy().to_string());
+                        }
+                    }
+                    Err(e) => {
+                        warnings.push(format!("Error processing glob pattern '{}': {}", pattern, e));
+                    }
+                }
+            }
+
+            if pattern_matches.is_empty() {
+                warnings.push(format!("No PDF, image or text files found matching pattern: {}", pattern));
+            } else {
+                sort_matches(&mut pattern_matches, order);
+                expanded_files.extend(pattern_matches);
+            }
+        } else {
+            // It's a literal file path, which must be a PDF or an image whatever its name, or a text file
+            let path = Path::new(&pattern);
+            anyhow::ensure!(path.is_file(), "Input file {} does not exist", pattern);
+            let kind = detect_input_kind(path)
+                .with_context(|| format!("Cannot read input file {}", pattern))?;
+            anyhow::ensure!(
+                kind.is_some(),
+                "Input file {} is not a PDF file, a JPEG, PNG or TIFF image or a .txt file",
+                pattern
+            );
+            expanded_files.push(pattern);
+        }
+    }
+
+    Ok((expanded_files, warnings))
+}
+
+/// File extensions of the inputs stapler loads, in any case.
+const INPUT_EXTENSIONS: [&str; 7] = ["pdf", "jpg", "jpeg", "png", "tif", "tiff", "txt"];
+
+/// Whether a matched file is taken as an input: by a PDF, image or text extension in any case,
+/// or by its contents for files named otherwise, such as extension-less document store
+/// exports.
+fn is_input_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        INPUT_EXTENSIONS
+            .iter()
+            .any(|known| ext.to_string_lossy().eq_ignore_ascii_case(known))
+    }) || detect_input_kind(path).ok().flatten().is_some()
+}
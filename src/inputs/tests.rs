@@ -0,0 +1,94 @@
+use std::{cmp::Ordering, env::temp_dir, fs};
+
+use super::*;
+
+#[test]
+fn test_natural_cmp_orders_numbers_by_value() {
+    let mut files = vec!["page10.pdf", "Page2.pdf", "page1.pdf", "page02b.pdf", "appendix.pdf"];
+    files.sort_by(|a, b| natural_cmp(a, b));
+    assert_eq!(files, ["appendix.pdf", "page1.pdf", "Page2.pdf", "page02b.pdf", "page10.pdf"]);
+    assert_eq!(natural_cmp("scan 7", "scan 7"), Ordering::Equal);
+    assert_eq!(natural_cmp("scan", "scan 1"), Ordering::Less);
+}
+
+#[test]
+fn test_expand_glob_patterns_sorts_each_pattern() {
+    let directory = temp_dir().join(format!("stapler-glob-order-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    for (name, size) in [("page10.pdf", 1), ("page2.pdf", 30), ("page1.PDF", 20), ("page3.doc", 5)] {
+        fs::write(directory.join(name), vec![b'%'; size]).unwrap();
+    }
+    fs::write(directory.join("cover.pdf"), b"%PDF-1.4").unwrap();
+    let pattern = directory.join("page*").to_string_lossy().to_string();
+    let cover = directory.join("cover.pdf").to_string_lossy().to_string();
+    let expand = |sort: SortOrder, reverse: bool| {
+        let patterns = vec![pattern.clone(), cover.clone()];
+        let (files, warnings) = expand_glob_patterns(patterns, InputOrder { sort, reverse }, &InputFilter::default()).unwrap();
+        assert!(warnings.is_empty());
+        files
+            .iter()
+            .map(|file| Path::new(file).file_name().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(expand(SortOrder::Name, false), ["page1.PDF", "page10.pdf", "page2.pdf", "cover.pdf"]);
+    assert_eq!(expand(SortOrder::Natural, false), ["page1.PDF", "page2.pdf", "page10.pdf", "cover.pdf"]);
+    assert_eq!(expand(SortOrder::Size, true), ["page2.pdf", "page1.PDF", "page10.pdf", "cover.pdf"]);
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert!("newest".parse::<SortOrder>().is_err());
+    assert_eq!("MTIME".parse::<SortOrder>().unwrap(), SortOrder::Modified);
+}
+
+#[test]
+fn test_expand_directories_recursively_with_excludes() {
+    let directory = temp_dir().join(format!("stapler-directory-input-{}", std::process::id()));
+    for subdirectory in ["2024", "2024/old", ".cache"] {
+        fs::create_dir_all(directory.join(subdirectory)).unwrap();
+    }
+    for name in ["a.pdf", "draft-b.pdf", "2024/c.pdf", "2024/draft.pdf", "2024/old/d.pdf", ".cache/e.pdf", ".f.pdf"] {
+        fs::write(directory.join(name), b"%PDF-1.4").unwrap();
+    }
+    let expand = |filter: InputFilter| {
+        let patterns = vec![directory.to_string_lossy().to_string()];
+        let (files, _) = expand_glob_patterns(patterns, InputOrder::default(), &filter).unwrap();
+        files
+            .iter()
+            .map(|file| Path::new(file).strip_prefix(&directory).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(expand(InputFilter::default()), ["a.pdf", "draft-b.pdf"]);
+    let filter = InputFilter {
+        recursive: true,
+        hidden: false,
+        exclude: vec![Pattern::new("**/draft*").unwrap(), Pattern::new("**/old/**").unwrap()],
+    };
+    assert_eq!(expand(filter.clone()), ["2024/c.pdf", "a.pdf"]);
+    assert_eq!(
+        expand(InputFilter { hidden: true, ..filter }),
+        [".cache/e.pdf", ".f.pdf", "2024/c.pdf", "a.pdf"]
+    );
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_detect_pdfs_by_header_and_reject_other_literal_inputs() {
+    let directory = temp_dir().join(format!("stapler-pdf-header-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join("export-0042"), b"\n%PDF-1.7\n").unwrap();
+    fs::write(directory.join("export-0043"), b"plain text").unwrap();
+    fs::write(directory.join("REPORT.Pdf"), b"%PDF-1.4").unwrap();
+    let path = |name: &str| directory.join(name).to_string_lossy().to_string();
+    let expand = |patterns: Vec<String>| expand_glob_patterns(patterns, InputOrder::default(), &InputFilter::default());
+
+    let (files, _) = expand(vec![path("*")]).unwrap();
+    assert_eq!(files, [path("REPORT.Pdf"), path("export-0042")]);
+    assert_eq!(expand(vec![path("export-0042")]).unwrap().0, [path("export-0042")]);
+
+    let error = expand(vec![path("export-0043")]).unwrap_err().to_string();
+    assert!(error.contains("is not a PDF file, a JPEG, PNG or TIFF image or a .txt file"), "{}", error);
+    let error = expand(vec![path("missing.pdf")]).unwrap_err().to_string();
+    assert!(error.contains("does not exist"), "{}", error);
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -1,21 +1,257 @@
+#[cfg(test)]
+mod tests;
+
+pub mod batch;
+pub mod inputs;
+pub mod manifest;
 pub mod merge;
+pub mod report;
 
 use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Instant;
 
 use anyhow::{Context, Result};
+use lopdf::Document;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 use merge::loader::{DocumentLoader, MergableDocument};
-use merge::{merge_documents, FileSystemOptions};
+use merge::compression::apply_compression;
+use merge::dedup::deduplicate_objects;
+use merge::images::optimize_images;
+use merge::metadata::{apply_metadata, DocumentMetadata, Timestamp};
+use merge::crop::add_margins;
+use merge::imposition::impose_pages;
+use merge::page_size::normalize_page_sizes;
+use merge::pdfa::{apply_pdfa, find_pdfa_violations};
+use merge::prune::prune_unreferenced_objects;
+use merge::save::write_document;
+use merge::version::{select_output_version, set_output_version, OutputVersion, PdfVersion};
+use merge::{combine_documents, StaplerOptions};
+use report::{InputReport, MergeReport, TimingReport};
+
+/// Runs `task` on a dedicated pool of `jobs` threads, or on the global rayon pool when
+/// no thread count is configured.
+pub(crate) fn with_thread_pool<T: Send>(
+    jobs: Option<usize>,
+    task: impl FnOnce() -> T + Send,
+) -> Result<T> {
+    match jobs {
+        Some(jobs) => Ok(ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .context("Failed to create thread pool")?
+            .install(task)),
+        None => Ok(task()),
+    }
+}
 
-pub fn stapler(options: FileSystemOptions) -> Result<File> {
-    let loaded_documents = options
+fn describe_inputs(
+    options: &StaplerOptions,
+    documents: &[MergableDocument],
+) -> Vec<InputReport> {
+    let mut next_page = 1;
+    options
         .input_sources
         .iter()
-        .map(|source| source.load())
-        .collect::<Vec<MergableDocument>>();
+        .zip(documents)
+        .map(|(source, document)| {
+            let page_count = document.get_page_count();
+            let report = InputReport {
+                file: source.input_file.clone(),
+                first_page: next_page,
+                last_page: next_page + page_count - 1,
+                page_count,
+                object_count: document.get_object_count(),
+                removed_blank_pages: document.get_removed_blank_pages().to_vec(),
+            };
+            next_page += page_count;
+            report
+        })
+        .collect()
+}
 
-    let mut document = merge_documents(loaded_documents, options.compress)?;
-    document
-        .save(options.destination.output_file)
-        .context("Failed to save output file")
+/// Turns the page spans of `inputs` into the spans of sheets showing their pages, given
+/// the sheet number of every merged page.
+fn place_inputs_on_sheets(inputs: &mut [InputReport], sheets: &[usize]) {
+    for input in inputs.iter_mut().filter(|input| input.page_count > 0) {
+        let input_sheets = &sheets[input.first_page - 1..input.last_page];
+        input.first_page = input_sheets.iter().copied().min().unwrap_or_default();
+        input.last_page = input_sheets.iter().copied().max().unwrap_or_default();
+    }
 }
 
+/// The output version, capped at PDF 1.7 for PDF/A-2 which builds on it.
+fn resolve_version(
+    options: &StaplerOptions,
+    documents: &[MergableDocument],
+) -> Result<OutputVersion> {
+    let inputs = options
+        .input_sources
+        .iter()
+        .map(|source| source.input_file.as_str())
+        .zip(documents)
+        .collect::<Vec<_>>();
+    let output_version = select_output_version(options.pdf_version, options.layout, &inputs);
+    if !options.pdfa || output_version.version <= PdfVersion::V1_7 {
+        return Ok(output_version);
+    }
+    anyhow::ensure!(
+        options.pdf_version.is_none(),
+        "PDF/A-2b output cannot be written as PDF {}, it requires PDF 1.7 or lower",
+        output_version.version
+    );
+    Ok(select_output_version(Some(PdfVersion::V1_7), options.layout, &inputs))
+}
+
+/// Fails with every PDF/A-2b violation of the merged `document`, naming the input each
+/// offending page came from.
+fn ensure_pdfa_conformance(document: &Document, inputs: &[InputReport]) -> Result<()> {
+    let violations = find_pdfa_violations(document);
+    if violations.is_empty() {
+        return Ok(());
+    }
+    let lines = violations
+        .iter()
+        .map(|violation| match violation.page {
+            Some(page) => {
+                let input = inputs
+                    .iter()
+                    .find(|input| (input.first_page..=input.last_page).contains(&page));
+                match input {
+                    Some(input) => format!(
+                        "  page {} ({} page {}): {}",
+                        page,
+                        input.file,
+                        page - input.first_page + 1,
+                        violation.reason
+                    ),
+                    None => format!("  page {}: {}", page, violation.reason),
+                }
+            }
+            None => format!("  document: {}", violation.reason),
+        })
+        .collect::<Vec<_>>();
+    anyhow::bail!(
+        "The merged document cannot conform to PDF/A-2b:\n{}",
+        lines.join("\n")
+    )
+}
+
+/// The configured metadata, completed from the input it should inherit from.
+fn resolve_metadata(
+    metadata: &DocumentMetadata,
+    documents: &[MergableDocument],
+) -> Result<DocumentMetadata> {
+    let mut metadata = metadata.clone();
+    if let Some(index) = metadata.inherit_from {
+        let source = index
+            .checked_sub(1)
+            .and_then(|index| documents.get(index))
+            .with_context(|| {
+                format!(
+                    "Cannot inherit metadata from input {}, there are {} inputs",
+                    index,
+                    documents.len()
+                )
+            })?;
+        metadata.inherit(source.get_metadata());
+    }
+    Ok(metadata)
+}
+
+pub fn stapler(options: StaplerOptions) -> Result<MergeReport> {
+    let load_started = Instant::now();
+    // Parsing dominates for large inputs; collecting keeps the input order for the merge.
+    let loaded_documents = with_thread_pool(options.jobs, || {
+        options
+            .input_sources
+            .par_iter()
+            .map(|source| {
+                let mut document = source.load()?;
+                if options.attach_inputs {
+                    document.attach_original_file(Path::new(&source.input_file))?;
+                }
+                if let Some(blank_pages) = &options.blank_pages {
+                    document.remove_blank_pages(blank_pages);
+                }
+                Ok(document)
+            })
+            .collect::<Result<Vec<MergableDocument>>>()
+    })??;
+    let mut inputs = describe_inputs(&options, &loaded_documents);
+    let metadata = resolve_metadata(&options.metadata, &loaded_documents)?;
+    let output_version = resolve_version(&options, &loaded_documents)?;
+    let load_time = load_started.elapsed();
+
+    let merge_started = Instant::now();
+    // Inputs left without pages by blank page removal stay in the report only.
+    let documents = loaded_documents
+        .into_iter()
+        .filter(|document| !document.is_empty())
+        .collect::<Vec<_>>();
+    anyhow::ensure!(!documents.is_empty(), "Every page of every input is blank");
+    let mut document = combine_documents(documents)?;
+    set_output_version(&mut document, output_version.version);
+    // Checked before pages are imposed, while every page is still a page of an input.
+    if options.pdfa {
+        ensure_pdfa_conformance(&document, &inputs)?;
+    }
+    if let Some(margins) = &options.margins {
+        add_margins(&mut document, margins);
+    }
+    if let Some(page_size) = &options.page_size {
+        normalize_page_sizes(&mut document, page_size);
+    }
+    if let Some(imposition) = &options.imposition {
+        let sheets = impose_pages(&mut document, imposition)?;
+        place_inputs_on_sheets(&mut inputs, &sheets);
+    }
+    // PDF/A needs the XMP metadata, other outputs only get what was asked for.
+    if options.pdfa || !options.metadata.is_empty() || options.metadata.inherit_from.is_some() {
+        apply_metadata(&mut document, &metadata, Timestamp::now(), options.pdfa);
+    }
+    if options.pdfa {
+        apply_pdfa(&mut document)?;
+    }
+    let pruning = options
+        .prune
+        .then(|| prune_unreferenced_objects(&mut document));
+    let deduplication = options
+        .deduplicate
+        .then(|| deduplicate_objects(&mut document));
+    let images = options
+        .images
+        .as_ref()
+        .map(|image_options| optimize_images(&mut document, image_options));
+    apply_compression(&mut document, &options.compression);
+    let merge_time = merge_started.elapsed();
+
+    let save_started = Instant::now();
+    let file = File::create(&options.destination.output_file)
+        .context("Failed to save output file")?;
+    let mut writer = BufWriter::new(file);
+    write_document(&mut document, &mut writer, output_version.layout)
+        .context("Failed to save output file")?;
+    let output_size = writer
+        .into_inner()
+        .context("Failed to save output file")?
+        .metadata()
+        .context("Failed to read output file metadata")?
+        .len();
+    let save_time = save_started.elapsed();
+
+    Ok(MergeReport {
+        output_file: options.destination.output_file.clone(),
+        page_count: document.get_pages().len(),
+        inputs,
+        object_count: document.objects.len(),
+        output_size,
+        deduplication,
+        pruning,
+        images,
+        warnings: output_version.warnings,
+        timings: TimingReport::from_durations(load_time, merge_time, save_time),
+    })
+}
@@ -1,75 +1,322 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
 use anyhow::{Context, Result};
 use clap::{Arg, ArgAction, Command};
-use glob::glob;
-use stapler::merge::FileSystemOptions;
+use stapler::batch::Batch;
+use stapler::inputs::{expand_glob_patterns, InputFilter, InputOrder, SortOrder};
+use stapler::manifest::load_manifest_options;
+use stapler::merge::attachments::{list_attachments, Attachment};
+use stapler::merge::blank::BlankPageOptions;
+use stapler::merge::compression::{CompressionLevel, StreamCompression};
+use stapler::merge::crop::{Length, PageCrop, PageMargins};
+use stapler::merge::image_pages::ImagePageOptions;
+use stapler::merge::images::ImageOptions;
+use stapler::merge::imposition::Imposition;
+use stapler::merge::metadata::DocumentMetadata;
+use stapler::merge::options::PageRotations;
+use stapler::merge::page_size::{PageFit, PageSize, PageSizeOptions};
+use stapler::merge::save::OutputLayout;
+use stapler::merge::text_pages::TextPageOptions;
+use stapler::merge::version::PdfVersion;
+use stapler::merge::StaplerOptions;
 use stapler::stapler;
 
-fn expand_glob_patterns(patterns: Vec<String>) -> Result<Vec<String>> {
-    let mut expanded_files = Vec::new();
-    
-    for pattern in patterns {
-        // Check if the pattern contains glob characters
-        if pattern.contains('*') || pattern.contains('?') || pattern.contains('[') {
-            // It's a glob pattern, expand it
-            let glob_matches = glob(&pattern)
-                .with_context(|| format!("Invalid glob pattern: {}", pattern))?;
-            
-            let mut pattern_matches = Vec::new();
-            for entry in glob_matches {
-                match entry {
-                    Ok(path) => {
-                        // Only include files that exist and have .pdf extension
-                        if path.is_file() && path.extension()
-                            .map_or(false, |ext| ext.to_string_lossy().to_lowercase() == "pdf") {
-                            pattern_matches.push(path.to_string_lossy().to_string());
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("[STAPLER] Warning: Error processing glob pattern '{}': {}", pattern, e);
-                    }
-                }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReportFormat {
+    Text,
+    Json,
+}
+
+enum CliJob {
+    Merge {
+        options: Box<StaplerOptions>,
+        warnings: Vec<String>,
+    },
+    Batch(Batch),
+    Attachments {
+        file: String,
+        password: Option<String>,
+        extract: Option<PathBuf>,
+    },
+}
+
+/// Output switches given on the command line. They add to whatever a manifest or
+/// batch job asks for, but never turn an option off.
+struct OutputFlags {
+    compress: bool,
+    compression_level: Option<CompressionLevel>,
+    decompress: bool,
+    keep_compressed_images: bool,
+    deduplicate: bool,
+    prune: bool,
+    object_streams: bool,
+    linearize: bool,
+    pdf_version: Option<PdfVersion>,
+    pdfa: bool,
+    attach_inputs: bool,
+    remove_blank_pages: bool,
+    blank_images: bool,
+    margin: Option<Length>,
+    gutter: Option<Length>,
+    page_size: Option<PageSize>,
+    page_fit: Option<PageFit>,
+    n_up: Option<Imposition>,
+    max_dpi: Option<u32>,
+    jpeg_quality: Option<u8>,
+    grayscale: bool,
+}
+
+impl OutputFlags {
+    fn apply(&self, options: &mut StaplerOptions) {
+        let compression = &mut options.compression;
+        if self.decompress {
+            compression.streams = StreamCompression::Decompress;
+        } else if let Some(level) = self.compression_level {
+            compression.streams = StreamCompression::Compress(level);
+        } else if self.compress && !matches!(compression.streams, StreamCompression::Compress(_)) {
+            compression.streams = StreamCompression::Compress(CompressionLevel::Default);
+        }
+        compression.keep_compressed_images |= self.keep_compressed_images;
+        options.deduplicate |= self.deduplicate;
+        options.prune |= self.prune;
+        if self.object_streams {
+            options.layout = OutputLayout::ObjectStreams;
+        }
+        if self.linearize {
+            options.layout = OutputLayout::Linearized;
+        }
+        if self.pdf_version.is_some() {
+            options.pdf_version = self.pdf_version;
+        }
+        options.pdfa |= self.pdfa;
+        options.attach_inputs |= self.attach_inputs;
+        if self.remove_blank_pages || self.blank_images {
+            let blank_pages = options.blank_pages.get_or_insert_with(BlankPageOptions::default);
+            blank_pages.images |= self.blank_images;
+        }
+        if self.margin.is_some() || self.gutter.is_some() {
+            let margins = options.margins.get_or_insert_with(PageMargins::default);
+            if let Some(Length(margin)) = self.margin {
+                margins.margin = margin;
             }
-            
-            if pattern_matches.is_empty() {
-                eprintln!("[STAPLER] Warning: No PDF files found matching pattern: {}", pattern);
-            } else {
-                // Sort the matches for consistent ordering
-                pattern_matches.sort();
-                expanded_files.extend(pattern_matches);
+            if let Some(Length(gutter)) = self.gutter {
+                margins.gutter = gutter;
             }
-        } else {
-            // It's a literal file path, add it directly
-            expanded_files.push(pattern);
+        }
+        if let Some(size) = self.page_size {
+            let fit = self.page_fit.unwrap_or_default();
+            options.page_size = Some(PageSizeOptions { size, fit });
+        } else if let (Some(fit), Some(page_size)) = (self.page_fit, &mut options.page_size) {
+            page_size.fit = fit;
+        }
+        if self.n_up.is_some() {
+            options.imposition = self.n_up;
+        }
+        if self.max_dpi.is_some() || self.jpeg_quality.is_some() || self.grayscale {
+            let images = options.images.get_or_insert_with(ImageOptions::default);
+            images.max_dpi = self.max_dpi.or(images.max_dpi);
+            images.jpeg_quality = self.jpeg_quality.unwrap_or(images.jpeg_quality);
+            images.grayscale |= self.grayscale;
         }
     }
-    
-    Ok(expanded_files)
 }
 
-fn parse_cli_arguments() -> Result<(Vec<String>, String, bool)> {
+struct CliArguments {
+    job: CliJob,
+    flags: OutputFlags,
+    report_format: ReportFormat,
+}
+
+fn parse_cli_arguments() -> Result<CliArguments> {
     let matches = Command::new("stapler")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("attachments")
+                .about("List the files embedded in a PDF, or extract them")
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .help("PDF file to read")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("extract")
+                        .short('x')
+                        .long("extract")
+                        .value_name("DIRECTORY")
+                        .help("Write every attachment into this directory, which is created if needed")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("password")
+                        .long("password")
+                        .value_name("PASSWORD")
+                        .help("Password of an encrypted file")
+                        .required(false),
+                ),
+        )
         .arg(
             Arg::new("input")
                 .short('i')
                 .long("input")
                 .value_name("FILES")
-                .help("Input PDF files or glob patterns (e.g., *.pdf, /path/to/*.pdf)")
+                .help("Input PDF, image (JPEG, PNG, TIFF) or text files, directories or glob patterns (e.g., *.pdf, /path/to/*.pdf, reports/)")
                 .num_args(1..)
                 .value_delimiter(' ')
-                .required(true),
+                .required_unless_present_any(["manifest", "batch"])
+                .conflicts_with_all(["manifest", "batch"]),
+        )
+        .arg(
+            Arg::new("recursive")
+                .action(ArgAction::SetTrue)
+                .short('r')
+                .long("recursive")
+                .help("Include PDF, image and text files in subdirectories of directory inputs")
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("exclude")
+                .action(ArgAction::Append)
+                .long("exclude")
+                .value_name("PATTERN")
+                .help("Leave out files whose path or name matches PATTERN (e.g. '**/draft*'); can be repeated")
+                .value_parser(|pattern: &str| glob::Pattern::new(pattern).map_err(|error| error.to_string()))
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("hidden")
+                .action(ArgAction::SetTrue)
+                .long("hidden")
+                .help("Include hidden files and directories of directory inputs")
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .value_name("ORDER")
+                .help("Order of the files each glob pattern or directory matches: name (default), natural, mtime, ctime, size or given")
+                .value_parser(|order: &str| order.parse::<SortOrder>().map_err(|error| format!("{:#}", error)))
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("reverse")
+                .action(ArgAction::SetTrue)
+                .long("reverse")
+                .help("Reverse the order of the files each glob pattern or directory matches")
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
         )
         .arg(
             Arg::new("output")
                 .short('o')
                 .long("output")
                 .value_name("FILE")
-                .help("Output PDF file")
-                .required(true),
+                .help("Output PDF file (overrides the manifest output)")
+                .required_unless_present_any(["manifest", "batch"])
+                .conflicts_with("batch"),
+        )
+        .arg(
+            Arg::new("manifest")
+                .short('m')
+                .long("manifest")
+                .value_name("FILE")
+                .help("Job description file (.toml, .yaml, .yml or .json) describing inputs and output")
+                .conflicts_with("batch")
+                .required(false),
+        )
+        .arg(
+            Arg::new("batch")
+                .short('b')
+                .long("batch")
+                .value_name("FILE")
+                .help("Batch file (.toml, .yaml, .yml, .json or .csv) listing several merge jobs to run in parallel")
+                .required(false),
+        )
+        .arg(
+            Arg::new("rotate")
+                .long("rotate")
+                .value_name("ROTATION")
+                .help("Rotate the pages of every input: 90, 180, 270, auto (landscape to portrait) or rules such as 1-3:90,5-:auto")
+                .value_parser(|rotation: &str| rotation.parse::<PageRotations>().map_err(|error| format!("{:#}", error)))
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("crop")
+                .long("crop")
+                .value_name("CROP")
+                .help("Crop the pages of every input: percentages cut from the edges (5%, 5%,10% or TOP,RIGHT,BOTTOM,LEFT) or a box LEFT,BOTTOM,RIGHT,TOP[pt|mm|in] to keep")
+                .value_parser(|crop: &str| crop.parse::<PageCrop>().map_err(|error| format!("{:#}", error)))
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("image-dpi")
+                .long("image-dpi")
+                .value_name("DPI")
+                .help("Resolution of image inputs, which sets the size of their pages [default: 300]")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("image-page-size")
+                .long("image-page-size")
+                .value_name("SIZE")
+                .help("Fit image inputs onto pages of this size (a4, letter, 210x297mm, ...), turned to each image's orientation")
+                .value_parser(|size: &str| size.parse::<PageSize>().map_err(|error| format!("{:#}", error)))
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("text-font-size")
+                .long("text-font-size")
+                .value_name("POINTS")
+                .help("Font size of .txt inputs [default: 10]")
+                .value_parser(|size: &str| match size.parse::<f32>() {
+                    Ok(size) if size > 0.0 => Ok(size),
+                    _ => Err(format!("'{}' is not a font size, expected a number of points above 0", size)),
+                })
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("text-margin")
+                .long("text-margin")
+                .value_name("LENGTH")
+                .help("White space around the text of .txt inputs, e.g. 15mm or 36 (points) [default: 0.5in]")
+                .value_parser(|length: &str| length.parse::<Length>().map_err(|error| format!("{:#}", error)))
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("text-page-size")
+                .long("text-page-size")
+                .value_name("SIZE")
+                .help("Page size of .txt inputs (a4, letter, 210x297mm, ...) [default: a4]")
+                .value_parser(|size: &str| size.parse::<PageSize>().map_err(|error| format!("{:#}", error)))
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("no-text-wrap")
+                .action(ArgAction::SetTrue)
+                .long("no-text-wrap")
+                .help("Cut off lines of .txt inputs that are wider than the page instead of wrapping them")
+                .conflicts_with_all(["manifest", "batch"])
+                .required(false),
         )
         .arg(
             Arg::new("compress")
@@ -79,63 +326,547 @@ fn parse_cli_arguments() -> Result<(Vec<String>, String, bool)> {
                 .help("Compress the output PDF file")
                 .required(false),
         )
+        .arg(
+            Arg::new("compression-level")
+                .long("compression-level")
+                .value_name("LEVEL")
+                .help("Compress the output PDF file with the given level (implies --compress)")
+                .value_parser(clap::value_parser!(CompressionLevel))
+                .required(false),
+        )
+        .arg(
+            Arg::new("decompress")
+                .action(ArgAction::SetTrue)
+                .long("decompress")
+                .help("Write all streams uncompressed, useful to inspect the output")
+                .conflicts_with_all(["compress", "compression-level"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("keep-compressed-images")
+                .action(ArgAction::SetTrue)
+                .long("keep-compressed-images")
+                .help("Leave image streams that are already compressed untouched")
+                .required(false),
+        )
+        .arg(
+            Arg::new("dedup")
+                .action(ArgAction::SetTrue)
+                .long("dedup")
+                .help("Collapse identical objects (fonts, images, ICC profiles) shared by the inputs")
+                .required(false),
+        )
+        .arg(
+            Arg::new("prune")
+                .action(ArgAction::SetTrue)
+                .long("prune")
+                .help("Remove objects that are not referenced from the merged document")
+                .required(false),
+        )
+        .arg(
+            Arg::new("object-streams")
+                .action(ArgAction::SetTrue)
+                .long("object-streams")
+                .help("Pack objects into object streams with a cross-reference stream (PDF 1.5)")
+                .required(false),
+        )
+        .arg(
+            Arg::new("linearize")
+                .action(ArgAction::SetTrue)
+                .long("linearize")
+                .help("Write a linearized PDF (fast web view) that shows the first page before it is fully downloaded")
+                .conflicts_with("object-streams")
+                .required(false),
+        )
+        .arg(
+            Arg::new("pdf-version")
+                .long("pdf-version")
+                .value_name("VERSION")
+                .help("PDF version of the output (1.0-1.7 or 2.0, defaults to the highest version of the inputs)")
+                .value_parser(|version: &str| version.parse::<PdfVersion>().map_err(|error| error.to_string()))
+                .required(false),
+        )
+        .arg(
+            Arg::new("pdfa")
+                .action(ArgAction::SetTrue)
+                .long("pdfa")
+                .help("Write PDF/A-2b for archiving; fails and lists the offending pages if an input prevents it")
+                .required(false),
+        )
+        .arg(
+            Arg::new("attach-inputs")
+                .action(ArgAction::SetTrue)
+                .long("attach-inputs")
+                .help("Embed every input file as an attachment of the output, with its name, modification date and checksum")
+                .required(false),
+        )
+        .arg(
+            Arg::new("remove-blank-pages")
+                .action(ArgAction::SetTrue)
+                .long("remove-blank-pages")
+                .help("Leave out pages that show nothing, such as blank separator sheets")
+                .required(false),
+        )
+        .arg(
+            Arg::new("blank-images")
+                .action(ArgAction::SetTrue)
+                .long("blank-images")
+                .help("Also leave out pages showing only near-white images, as scanned blank sheets do (implies --remove-blank-pages)")
+                .required(false),
+        )
+        .arg(
+            Arg::new("margin")
+                .long("margin")
+                .value_name("LENGTH")
+                .help("Add white space around every page, e.g. 10mm, 0.5in or 20 (points)")
+                .value_parser(|length: &str| length.parse::<Length>().map_err(|error| format!("{:#}", error)))
+                .required(false),
+        )
+        .arg(
+            Arg::new("gutter")
+                .long("gutter")
+                .value_name("LENGTH")
+                .help("Add a binding gutter: extra space on the left of odd pages and the right of even pages")
+                .value_parser(|length: &str| length.parse::<Length>().map_err(|error| format!("{:#}", error)))
+                .required(false),
+        )
+        .arg(
+            Arg::new("page-size")
+                .long("page-size")
+                .value_name("SIZE")
+                .help("Scale every page onto one size: a4, a3, a5, letter, legal or WIDTHxHEIGHT[pt|mm|in]")
+                .value_parser(|size: &str| size.parse::<PageSize>().map_err(|error| format!("{:#}", error)))
+                .required(false),
+        )
+        .arg(
+            Arg::new("page-fit")
+                .long("page-fit")
+                .value_name("FIT")
+                .help("How pages are scaled onto --page-size: fit shows the whole page, fill covers the page and crops")
+                .value_parser(["fit", "fill"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("n-up")
+                .long("n-up")
+                .value_name("LAYOUT")
+                .help("Place several pages on each sheet: 2, 4, COLUMNSxROWS, or booklet for saddle-stitch printing")
+                .value_parser(|layout: &str| layout.parse::<Imposition>().map_err(|error| format!("{:#}", error)))
+                .required(false),
+        )
+        .arg(
+            Arg::new("max-dpi")
+                .long("max-dpi")
+                .value_name("DPI")
+                .help("Downsample images shown at a higher resolution than DPI")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .required(false),
+        )
+        .arg(
+            Arg::new("jpeg-quality")
+                .long("jpeg-quality")
+                .value_name("QUALITY")
+                .help("Recompress photographic images as JPEG with this quality (1-100, default 75)")
+                .value_parser(clap::value_parser!(u8).range(1..=100))
+                .required(false),
+        )
+        .arg(
+            Arg::new("grayscale")
+                .action(ArgAction::SetTrue)
+                .long("grayscale")
+                .help("Convert color images to grayscale")
+                .required(false),
+        )
+        .args(
+            [
+                ("title", "Title of the output document"),
+                ("author", "Author of the output document"),
+                ("subject", "Subject of the output document"),
+                ("keywords", "Keywords of the output document"),
+                ("creator", "Application that created the original content"),
+                ("producer", "Application that produced the output (defaults to stapler)"),
+            ]
+            .map(|(name, help)| {
+                Arg::new(name)
+                    .long(name)
+                    .value_name("TEXT")
+                    .help(help)
+                    .conflicts_with("batch")
+                    .required(false)
+            }),
+        )
+        .arg(
+            Arg::new("metadata-from")
+                .long("metadata-from")
+                .value_name("N")
+                .help("Take the metadata not given on the command line from the N-th input")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .conflicts_with("batch")
+                .required(false),
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .value_name("N")
+                .help("Number of threads used to load inputs and run batch jobs (defaults to one per CPU)")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .required(false),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .value_name("FORMAT")
+                .help("Format of the summary printed to stdout")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .global(true)
+                .required(false),
+        )
         .get_matches();
 
-    let input_patterns: Vec<String> = matches
-        .get_many::<String>("input")
-        .context("No input files provided")?
-        .cloned()
-        .collect();
-
-    // Expand glob patterns
-    let input_files = expand_glob_patterns(input_patterns)?;
-    
-    if input_files.is_empty() {
-        anyhow::bail!("No PDF files found after expanding patterns");
-    }
-    
-    if input_files.len() < 2 {
-        anyhow::bail!("At least 2 PDF files are required for merging");
+    let flags = OutputFlags {
+        compress: matches.get_flag("compress"),
+        compression_level: matches.get_one::<CompressionLevel>("compression-level").copied(),
+        decompress: matches.get_flag("decompress"),
+        keep_compressed_images: matches.get_flag("keep-compressed-images"),
+        deduplicate: matches.get_flag("dedup"),
+        prune: matches.get_flag("prune"),
+        object_streams: matches.get_flag("object-streams"),
+        linearize: matches.get_flag("linearize"),
+        pdf_version: matches.get_one::<PdfVersion>("pdf-version").copied(),
+        pdfa: matches.get_flag("pdfa"),
+        attach_inputs: matches.get_flag("attach-inputs"),
+        remove_blank_pages: matches.get_flag("remove-blank-pages"),
+        blank_images: matches.get_flag("blank-images"),
+        margin: matches.get_one::<Length>("margin").copied(),
+        gutter: matches.get_one::<Length>("gutter").copied(),
+        page_size: matches.get_one::<PageSize>("page-size").copied(),
+        page_fit: matches
+            .get_one::<String>("page-fit")
+            .map(|fit| fit.parse())
+            .transpose()?,
+        n_up: matches.get_one::<Imposition>("n-up").copied(),
+        max_dpi: matches.get_one::<u32>("max-dpi").copied(),
+        jpeg_quality: matches.get_one::<u8>("jpeg-quality").copied(),
+        grayscale: matches.get_flag("grayscale"),
+    };
+    let jobs = matches.get_one::<u32>("jobs").map(|jobs| *jobs as usize);
+    let text = |name: &str| matches.get_one::<String>(name).cloned();
+    let metadata = DocumentMetadata {
+        title: text("title"),
+        author: text("author"),
+        subject: text("subject"),
+        keywords: text("keywords"),
+        creator: text("creator"),
+        producer: text("producer"),
+        inherit_from: matches.get_one::<u32>("metadata-from").map(|index| *index as usize),
+    };
+
+    let job = if let Some(("attachments", attachments)) = matches.subcommand() {
+        CliJob::Attachments {
+            file: attachments.get_one::<String>("file").context("No file provided")?.clone(),
+            password: attachments.get_one::<String>("password").cloned(),
+            extract: attachments.get_one::<PathBuf>("extract").cloned(),
+        }
+    } else if let Some(batch) = matches.get_one::<String>("batch") {
+        let mut batch = Batch::load(Path::new(batch))?;
+        batch.threads = jobs;
+        CliJob::Batch(batch)
+    } else if let Some(manifest) = matches.get_one::<String>("manifest") {
+        let mut options = load_manifest_options(Path::new(manifest))?;
+        if let Some(output_file) = matches.get_one::<String>("output") {
+            options.destination.output_file = output_file.clone();
+        }
+        options.jobs = jobs;
+        options.metadata.override_with(metadata);
+        CliJob::Merge {
+            options: Box::new(options),
+            warnings: Vec::new(),
+        }
+    } else {
+        let input_patterns: Vec<String> = matches
+            .get_many::<String>("input")
+            .context("No input files provided")?
+            .cloned()
+            .collect();
+
+        // Expand glob patterns
+        let input_order = InputOrder {
+            sort: matches.get_one::<SortOrder>("sort").copied().unwrap_or_default(),
+            reverse: matches.get_flag("reverse"),
+        };
+        let input_filter = InputFilter {
+            recursive: matches.get_flag("recursive"),
+            hidden: matches.get_flag("hidden"),
+            exclude: matches
+                .get_many::<glob::Pattern>("exclude")
+                .map(|patterns| patterns.cloned().collect())
+                .unwrap_or_default(),
+        };
+        let (input_files, warnings) = expand_glob_patterns(input_patterns, input_order, &input_filter)?;
+
+        if input_files.is_empty() {
+            anyhow::bail!("No input files found after expanding patterns");
+        }
+
+        if input_files.len() < 2 {
+            anyhow::bail!("At least 2 input files are required for merging");
+        }
+
+        let output_file: String = matches
+            .get_one::<String>("output")
+            .context("No output file provided")?
+            .clone();
+
+        let mut options = StaplerOptions::from((&input_files, &output_file, false));
+        if let Some(rotation) = matches.get_one::<PageRotations>("rotate") {
+            for source in &mut options.input_sources {
+                source.rotation = Some(rotation.clone());
+            }
+        }
+        if let Some(crop) = matches.get_one::<PageCrop>("crop") {
+            for source in &mut options.input_sources {
+                source.crop = Some(*crop);
+            }
+        }
+        let image = ImagePageOptions {
+            dpi: matches.get_one::<u32>("image-dpi").copied().unwrap_or(ImagePageOptions::default().dpi),
+            page_size: matches.get_one::<PageSize>("image-page-size").copied(),
+        };
+        let defaults = TextPageOptions::default();
+        let text = TextPageOptions {
+            font_size: matches.get_one::<f32>("text-font-size").copied().unwrap_or(defaults.font_size),
+            margin: matches.get_one::<Length>("text-margin").copied().unwrap_or(defaults.margin),
+            page_size: matches.get_one::<PageSize>("text-page-size").copied().unwrap_or(defaults.page_size),
+            wrap: !matches.get_flag("no-text-wrap"),
+        };
+        for source in &mut options.input_sources {
+            source.image = image;
+            source.text = text;
+        }
+        options.jobs = jobs;
+        options.metadata = metadata;
+        CliJob::Merge {
+            options: Box::new(options),
+            warnings,
+        }
+    };
+
+    let report_format = match matches.get_one::<String>("report").map(String::as_str) {
+        Some("json") => ReportFormat::Json,
+        _ => ReportFormat::Text,
+    };
+
+    Ok(CliArguments {
+        job,
+        flags,
+        report_format,
+    })
+}
+
+fn run_merge(
+    mut options: StaplerOptions,
+    warnings: Vec<String>,
+    flags: &OutputFlags,
+    report_format: ReportFormat,
+) -> Result<()> {
+    flags.apply(&mut options);
+    let input_files = options
+        .input_sources
+        .iter()
+        .map(|source| source.input_file.as_str())
+        .collect::<Vec<&str>>();
+    let output_file = options.destination.output_file.clone();
+
+    if report_format == ReportFormat::Text {
+        for warning in &warnings {
+            eprintln!("[STAPLER] Warning: {}", warning);
+        }
+
+        println!(
+            "[STAPLER] Found {} input files to merge into {}",
+            input_files.len(), output_file
+        );
+
+        if input_files.len() <= 10 {
+            println!("[STAPLER] Input files: {:?}", input_files);
+        } else {
+            println!("[STAPLER] Input files: {} files (showing first 5): {:?}...",
+                     input_files.len(), &input_files[..5]);
+        }
     }
 
-    let output_file: String = matches
-        .get_one::<String>("output")
-        .context("No output file provided")?
-        .clone();
+    let mut report = match stapler(options) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("[STAPLER] Error: {:#}", e);
+            exit(1);
+        }
+    };
 
-    let compress = matches
-        .get_one::<bool>("compress")
-        .copied()
-        .unwrap_or(false);
+    match report_format {
+        ReportFormat::Text => {
+            for warning in &report.warnings {
+                eprintln!("[STAPLER] Warning: {}", warning);
+            }
+            for input in report.inputs.iter().filter(|input| !input.removed_blank_pages.is_empty()) {
+                if input.page_count == 0 {
+                    println!("[STAPLER] Left out {}, every page is blank", input.file);
+                    continue;
+                }
+                let pages = input
+                    .removed_blank_pages
+                    .iter()
+                    .map(u32::to_string)
+                    .collect::<Vec<_>>();
+                println!(
+                    "[STAPLER] Removed blank pages of {}: {}",
+                    input.file,
+                    pages.join(", ")
+                );
+            }
+            println!(
+                "[STAPLER] PDFs merged successfully. Output file: {}",
+                output_file
+            );
+        }
+        ReportFormat::Json => {
+            report.warnings.splice(0..0, warnings);
+            println!("{}", report.to_json()?);
+        }
+    }
 
-    Ok((input_files, output_file, compress))
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let (input_files, output_file, compress) = parse_cli_arguments()?;
-    let file_options = FileSystemOptions::from((&input_files, &output_file, compress));
-
-    println!(
-        "[STAPLER] Found {} PDF files to merge into {}",
-        input_files.len(), output_file
-    );
-    
-    if input_files.len() <= 10 {
-        println!("[STAPLER] Input files: {:?}", input_files);
-    } else {
-        println!("[STAPLER] Input files: {} files (showing first 5): {:?}...", 
-                 input_files.len(), &input_files[..5]);
+fn run_batch(batch: Batch, flags: &OutputFlags, report_format: ReportFormat) -> Result<()> {
+    if report_format == ReportFormat::Text {
+        println!("[STAPLER] Running {} merge jobs", batch.jobs.len());
     }
 
-    if let Err(e) = stapler(file_options) {
-        eprintln!("[STAPLER] Error: {}", e);
+    let report = batch.run(|options| flags.apply(options))?;
+
+    match report_format {
+        ReportFormat::Text => {
+            let total = report.jobs.len();
+            for (index, job) in report.jobs.iter().enumerate() {
+                match (&job.report, &job.error) {
+                    (Some(merge_report), _) => {
+                        for warning in &merge_report.warnings {
+                            eprintln!("[STAPLER] Warning ({}): {}", job.output_file, warning);
+                        }
+                        println!(
+                            "[STAPLER] Job {}/{} succeeded: {} ({} pages)",
+                            index + 1, total, job.output_file, merge_report.page_count
+                        );
+                    }
+                    (None, error) => {
+                        eprintln!(
+                            "[STAPLER] Job {}/{} failed: {}: {}",
+                            index + 1, total, job.output_file, error.as_deref().unwrap_or("unknown error")
+                        );
+                    }
+                }
+            }
+            println!(
+                "[STAPLER] Batch finished: {} succeeded, {} failed",
+                report.succeeded, report.failed
+            );
+        }
+        ReportFormat::Json => println!("{}", report.to_json()?),
+    }
+
+    if report.has_failures() {
         exit(1);
     }
 
-    println!(
-        "[STAPLER] PDFs merged successfully. Output file: {}",
-        output_file
-    );
+    Ok(())
+}
+
+/// Where an attachment is extracted to: its file name without any directories, numbered
+/// when an earlier attachment took the name.
+fn extraction_path(directory: &Path, attachment: &Attachment, taken: &mut HashSet<PathBuf>) -> PathBuf {
+    let file_name = Path::new(&attachment.file_name)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "attachment".to_string());
+    let mut path = directory.join(&file_name);
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem.to_string(), format!(".{}", extension)),
+        _ => (file_name.clone(), String::new()),
+    };
+    let mut number = 2;
+    while taken.contains(&path) {
+        path = directory.join(format!("{} ({}){}", stem, number, extension));
+        number += 1;
+    }
+    taken.insert(path.clone());
+    path
+}
+
+fn run_attachments(
+    file: &str,
+    password: Option<&str>,
+    extract: Option<&Path>,
+    report_format: ReportFormat,
+) -> Result<()> {
+    let document = match password {
+        Some(password) => lopdf::Document::load_with_password(file, password),
+        None => lopdf::Document::load(file),
+    }
+    .with_context(|| format!("Failed to load {}", file))?;
+    let attachments = list_attachments(&document)?;
 
+    let mut paths = Vec::new();
+    if let Some(directory) = extract {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("Cannot create directory {}", directory.display()))?;
+        let mut taken = HashSet::new();
+        for attachment in &attachments {
+            let path = extraction_path(directory, attachment, &mut taken);
+            fs::write(&path, &attachment.data)
+                .with_context(|| format!("Cannot write {}", path.display()))?;
+            paths.push(path);
+        }
+    }
+
+    match report_format {
+        ReportFormat::Text => {
+            println!("[STAPLER] {} attachments in {}", attachments.len(), file);
+            for (index, attachment) in attachments.iter().enumerate() {
+                let mut details = vec![format!("{} bytes", attachment.size)];
+                details.extend(attachment.mime_type.clone());
+                details.extend(attachment.modified.as_ref().map(|date| format!("modified {}", date)));
+                println!("  {} ({})", attachment.file_name, details.join(", "));
+                if let Some(description) = &attachment.description {
+                    println!("    {}", description);
+                }
+                if let Some(path) = paths.get(index) {
+                    println!("    extracted to {}", path.display());
+                }
+            }
+        }
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&attachments)?),
+    }
     Ok(())
 }
+
+fn main() -> Result<()> {
+    let CliArguments {
+        job,
+        flags,
+        report_format,
+    } = parse_cli_arguments()?;
+
+    match job {
+        CliJob::Merge { options, warnings } => run_merge(*options, warnings, &flags, report_format),
+        CliJob::Batch(batch) => run_batch(batch, &flags, report_format),
+        CliJob::Attachments {
+            file,
+            password,
+            extract,
+        } => run_attachments(&file, password.as_deref(), extract.as_deref(), report_format),
+    }
+}
@@ -0,0 +1,295 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::merge::{
+    blank::BlankPageOptions,
+    compression::{CompressionLevel, CompressionOptions, StreamCompression},
+    crop::{Length, PageCrop, PageMargins},
+    image_pages::ImagePageOptions,
+    images::ImageOptions,
+    imposition::Imposition,
+    loader::fs::{FileSystemMergingDestination, FileSystemMergingSource},
+    metadata::DocumentMetadata,
+    page_size::{PageFit, PageSize, PageSizeOptions},
+    options::{PageRanges, PageRotations},
+    save::OutputLayout,
+    text_pages::TextPageOptions,
+    version::PdfVersion,
+    StaplerOptions,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ManifestFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("toml") => Ok(ManifestFormat::Toml),
+            Some("yaml" | "yml") => Ok(ManifestFormat::Yaml),
+            Some("json") => Ok(ManifestFormat::Json),
+            _ => anyhow::bail!(
+                "Unsupported manifest format for {}: expected a .toml, .yaml, .yml or .json file",
+                path.display()
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestOutput {
+    pub file: String,
+    #[serde(default)]
+    pub compress: bool,
+    /// Flate level used when compressing, implies `compress`.
+    pub compression_level: Option<CompressionLevel>,
+    /// Write all streams uncompressed, for debugging.
+    #[serde(default)]
+    pub decompress: bool,
+    /// Leave image streams that are already compressed untouched.
+    #[serde(default)]
+    pub keep_compressed_images: bool,
+    #[serde(default)]
+    pub deduplicate: bool,
+    #[serde(default)]
+    pub prune: bool,
+    /// Pack objects into object streams with a cross-reference stream.
+    #[serde(default)]
+    pub object_streams: bool,
+    /// Write a linearized file for fast web view.
+    #[serde(default)]
+    pub linearize: bool,
+    /// PDF version of the output, defaults to the highest version of the inputs.
+    pub pdf_version: Option<PdfVersion>,
+    /// Write PDF/A-2b and fail when the inputs keep the result from conforming.
+    #[serde(default)]
+    pub pdfa: bool,
+    /// Embed the input files as attachments, with their name, modification date and checksum.
+    #[serde(default)]
+    pub attach_inputs: bool,
+    /// Leave out pages that show nothing.
+    #[serde(default)]
+    pub remove_blank_pages: bool,
+    /// Also leave out pages showing only near-white images, implies `remove_blank_pages`.
+    #[serde(default)]
+    pub blank_images: bool,
+    /// White space around every page (`10mm`, `0.5in`, or a number of points).
+    pub margin: Option<Length>,
+    /// Extra space on the binding side: left on odd pages, right on even pages.
+    pub gutter: Option<Length>,
+    /// Scale every page onto this size (`a4`, `letter`, `210x297mm`, ...).
+    pub page_size: Option<PageSize>,
+    /// How pages are scaled onto `page_size`, `fit` (default) or `fill`.
+    pub page_fit: Option<PageFit>,
+    /// Pages per sheet: `2`, `4`, `COLUMNSxROWS` or `booklet`.
+    pub n_up: Option<Imposition>,
+    /// Downsample and recompress images, see [`ImageOptions`].
+    pub images: Option<ImageOptions>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestInput {
+    pub file: String,
+    pub pages: Option<PageRanges>,
+    /// Degrees for every page, `auto`, or per-range rules such as `1-3:90,5-:auto`.
+    pub rotate: Option<PageRotations>,
+    /// Percentages cut from the edges (`5%`, `5%,10%`) or the box to keep (`10,10,200,287mm`).
+    pub crop: Option<PageCrop>,
+    /// Resolution and page size for image inputs, `{ dpi = 300, page_size = "a4" }`.
+    #[serde(default)]
+    pub image: ImagePageOptions,
+    /// Layout of `.txt` inputs, `{ font_size = 9, margin = "15mm", page_size = "a4", wrap = true }`.
+    #[serde(default)]
+    pub text: TextPageOptions,
+    pub bookmark: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub output: ManifestOutput,
+    #[serde(default)]
+    pub metadata: DocumentMetadata,
+    pub inputs: Vec<ManifestInput>,
+}
+
+pub(crate) fn resolve_path(base_dir: &Path, file: &str) -> PathBuf {
+    let path = Path::new(file);
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base_dir.join(path)
+    }
+}
+
+pub(crate) fn deserialize<T: DeserializeOwned>(contents: &str, format: ManifestFormat) -> Result<T> {
+    match format {
+        ManifestFormat::Toml => toml::from_str(contents).map_err(anyhow::Error::from),
+        ManifestFormat::Yaml => serde_yaml::from_str(contents).map_err(anyhow::Error::from),
+        ManifestFormat::Json => serde_json::from_str(contents).map_err(anyhow::Error::from),
+    }
+}
+
+impl Manifest {
+    pub fn parse(contents: &str, format: ManifestFormat) -> Result<Self> {
+        deserialize(contents, format)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let format = ManifestFormat::from_path(path)?;
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read manifest {}", path.display()))?;
+        Manifest::parse(&contents, format)
+            .with_context(|| format!("Invalid manifest {}", path.display()))
+    }
+
+    /// Validates the manifest and turns it into merge options. Relative paths are
+    /// resolved against `base_dir`, usually the directory containing the manifest.
+    pub fn into_options(self, base_dir: &Path) -> Result<StaplerOptions> {
+        anyhow::ensure!(
+            !self.output.file.trim().is_empty(),
+            "output.file must not be empty"
+        );
+        anyhow::ensure!(
+            self.inputs.len() >= 2,
+            "inputs must list at least 2 files, found {}",
+            self.inputs.len()
+        );
+
+        anyhow::ensure!(
+            !(self.output.decompress
+                && (self.output.compress || self.output.compression_level.is_some())),
+            "output.decompress cannot be combined with output.compress or output.compression_level"
+        );
+        anyhow::ensure!(
+            !(self.output.object_streams && self.output.linearize),
+            "output.object_streams cannot be combined with output.linearize"
+        );
+        anyhow::ensure!(
+            self.output.page_fit.is_none() || self.output.page_size.is_some(),
+            "output.page_fit requires output.page_size"
+        );
+        if let Some(index) = self.metadata.inherit_from {
+            anyhow::ensure!(
+                (1..=self.inputs.len()).contains(&index),
+                "metadata.inherit_from must refer to one of the {} inputs, found {}",
+                self.inputs.len(),
+                index
+            );
+        }
+        if let Some(images) = &self.output.images {
+            images
+                .validate()
+                .map_err(|error| anyhow::anyhow!("output.images.{}", error))?;
+        }
+
+        let input_sources = self
+            .inputs
+            .into_iter()
+            .enumerate()
+            .map(|(index, input)| {
+                anyhow::ensure!(
+                    !input.file.trim().is_empty(),
+                    "inputs[{}].file must not be empty",
+                    index
+                );
+                let input_file = resolve_path(base_dir, &input.file);
+                anyhow::ensure!(
+                    input_file.is_file(),
+                    "inputs[{}].file: {} does not exist or is not a file",
+                    index,
+                    input_file.display()
+                );
+                if let Some(bookmark) = &input.bookmark {
+                    anyhow::ensure!(
+                        !bookmark.trim().is_empty(),
+                        "inputs[{}].bookmark must not be empty",
+                        index
+                    );
+                }
+
+                Ok(FileSystemMergingSource {
+                    input_file: input_file.to_string_lossy().to_string(),
+                    pages: input.pages,
+                    rotation: input.rotate,
+                    crop: input.crop,
+                    image: input.image,
+                    text: input.text,
+                    bookmark: input.bookmark,
+                    password: input.password,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(StaplerOptions {
+            input_sources,
+            destination: FileSystemMergingDestination {
+                output_file: resolve_path(base_dir, &self.output.file)
+                    .to_string_lossy()
+                    .to_string(),
+            },
+            metadata: self.metadata,
+            compression: CompressionOptions {
+                streams: if self.output.decompress {
+                    StreamCompression::Decompress
+                } else if self.output.compress || self.output.compression_level.is_some() {
+                    StreamCompression::Compress(self.output.compression_level.unwrap_or_default())
+                } else {
+                    StreamCompression::Unchanged
+                },
+                keep_compressed_images: self.output.keep_compressed_images,
+            },
+            deduplicate: self.output.deduplicate,
+            prune: self.output.prune,
+            attach_inputs: self.output.attach_inputs,
+            blank_pages: (self.output.remove_blank_pages || self.output.blank_images).then_some(BlankPageOptions {
+                images: self.output.blank_images,
+            }),
+            margins: (self.output.margin.is_some() || self.output.gutter.is_some()).then(|| PageMargins {
+                margin: self.output.margin.map_or(0.0, |Length(points)| points),
+                gutter: self.output.gutter.map_or(0.0, |Length(points)| points),
+            }),
+            page_size: self.output.page_size.map(|size| PageSizeOptions {
+                size,
+                fit: self.output.page_fit.unwrap_or_default(),
+            }),
+            imposition: self.output.n_up,
+            images: self.output.images,
+            layout: if self.output.object_streams {
+                OutputLayout::ObjectStreams
+            } else if self.output.linearize {
+                OutputLayout::Linearized
+            } else {
+                OutputLayout::Classic
+            },
+            pdf_version: self.output.pdf_version,
+            pdfa: self.output.pdfa,
+            ..Default::default()
+        })
+    }
+}
+
+/// Reads, parses and validates the manifest at `path` in one go.
+pub fn load_manifest_options(path: &Path) -> Result<StaplerOptions> {
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    Manifest::load(path)?
+        .into_options(base_dir)
+        .with_context(|| format!("Invalid manifest {}", path.display()))
+}
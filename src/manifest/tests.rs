@@ -0,0 +1,119 @@
+use std::{env::temp_dir, fs};
+
+use super::*;
+use crate::merge::options::Rotation;
+
+const TOML_MANIFEST: &str = r#"
+[output]
+file = "bundle.pdf"
+compress = true
+
+[metadata]
+title = "Quarterly bundle"
+author = "Accounting"
+
+[[inputs]]
+file = "cover.pdf"
+bookmark = "Cover"
+
+[[inputs]]
+file = "scan.pdf"
+pages = "1-3,5"
+rotate = 90
+password = "secret"
+"#;
+
+fn error_chain(error: anyhow::Error) -> String {
+    format!("{:#}", error)
+}
+
+#[test]
+fn test_parse_toml_manifest() {
+    let manifest = Manifest::parse(TOML_MANIFEST, ManifestFormat::Toml).unwrap();
+
+    assert_eq!(manifest.output.file, "bundle.pdf");
+    assert!(manifest.output.compress);
+    assert_eq!(manifest.metadata.title.as_deref(), Some("Quarterly bundle"));
+    assert_eq!(manifest.inputs.len(), 2);
+    assert_eq!(manifest.inputs[0].bookmark.as_deref(), Some("Cover"));
+    assert_eq!(manifest.inputs[1].rotate, Some(Rotation::Clockwise90.into()));
+
+    let pages = manifest.inputs[1].pages.as_ref().unwrap();
+    assert!(pages.contains(2) && pages.contains(5));
+    assert!(!pages.contains(4));
+}
+
+#[test]
+fn test_parse_yaml_and_json_manifests() {
+    let yaml = "output:\n  file: out.pdf\n  pdf_version: 1.7\ninputs:\n  - file: a.pdf\n  - file: b.pdf\n    pages: \"2-\"\n";
+    let json = r#"{"output": {"file": "out.pdf", "pdf_version": "2.0"}, "inputs": [{"file": "a.pdf"}, {"file": "b.pdf", "rotate": 270}]}"#;
+
+    let from_yaml = Manifest::parse(yaml, ManifestFormat::Yaml).unwrap();
+    let from_json = Manifest::parse(json, ManifestFormat::Json).unwrap();
+
+    assert!(from_yaml.inputs[1].pages.as_ref().unwrap().contains(200));
+    assert_eq!(from_json.inputs[1].rotate, Some(Rotation::Clockwise270.into()));
+    assert_eq!(from_yaml.output.pdf_version.map(|version| version.to_string()).as_deref(), Some("1.7"));
+    assert_eq!(from_json.output.pdf_version.map(|version| version.to_string()).as_deref(), Some("2.0"));
+}
+
+#[test]
+fn test_reject_invalid_manifest_values() {
+    let bad_rotation = r#"{"output": {"file": "o.pdf"}, "inputs": [{"file": "a.pdf", "rotate": 45}]}"#;
+    let bad_pages = r#"{"output": {"file": "o.pdf"}, "inputs": [{"file": "a.pdf", "pages": "3-1"}]}"#;
+    let unknown_field = r#"{"output": {"file": "o.pdf"}, "inputs": [{"file": "a.pdf", "rotation": 90}]}"#;
+
+    let error = error_chain(Manifest::parse(bad_rotation, ManifestFormat::Json).unwrap_err());
+    assert!(error.contains("rotation must be one of 90, 180 or 270 degrees, got 45"), "{}", error);
+
+    let bad_rule = r#"{"output": {"file": "o.pdf"}, "inputs": [{"file": "a.pdf", "rotate": "1-2:auto,3:45"}]}"#;
+    let error = error_chain(Manifest::parse(bad_rule, ManifestFormat::Json).unwrap_err());
+    assert!(error.contains("Invalid rotation '3:45'"), "{}", error);
+
+    let error = error_chain(Manifest::parse(bad_pages, ManifestFormat::Json).unwrap_err());
+    assert!(error.contains("range ends before it starts"), "{}", error);
+
+    let error = error_chain(Manifest::parse(unknown_field, ManifestFormat::Json).unwrap_err());
+    assert!(error.contains("unknown field `rotation`"), "{}", error);
+}
+
+#[test]
+fn test_manifest_into_options_resolves_and_validates_inputs() {
+    let base_dir = temp_dir().join(format!("stapler-manifest-test-{}", std::process::id()));
+    fs::create_dir_all(&base_dir).unwrap();
+    fs::write(base_dir.join("cover.pdf"), b"%PDF-1.5").unwrap();
+    fs::write(base_dir.join("scan.pdf"), b"%PDF-1.5").unwrap();
+
+    let options = Manifest::parse(TOML_MANIFEST, ManifestFormat::Toml)
+        .unwrap()
+        .into_options(&base_dir)
+        .unwrap();
+
+    assert_eq!(options.input_sources.len(), 2);
+    assert_eq!(
+        options.input_sources[1].input_file,
+        base_dir.join("scan.pdf").to_string_lossy()
+    );
+    assert_eq!(options.input_sources[1].password.as_deref(), Some("secret"));
+    assert!(options.destination.output_file.ends_with("bundle.pdf"));
+    assert_eq!(
+        options.compression.streams,
+        StreamCompression::Compress(CompressionLevel::Default)
+    );
+
+    let missing = TOML_MANIFEST.replace("scan.pdf", "missing.pdf");
+    let error = Manifest::parse(&missing, ManifestFormat::Toml)
+        .unwrap()
+        .into_options(&base_dir)
+        .unwrap_err();
+    assert!(error.to_string().starts_with("inputs[1].file:"), "{}", error);
+
+    let conflicting = TOML_MANIFEST.replace("compress = true", "compress = true\ndecompress = true");
+    let error = Manifest::parse(&conflicting, ManifestFormat::Toml)
+        .unwrap()
+        .into_options(&base_dir)
+        .unwrap_err();
+    assert!(error.to_string().starts_with("output.decompress cannot be combined"), "{}", error);
+
+    fs::remove_dir_all(base_dir).unwrap();
+}
@@ -0,0 +1,256 @@
+use std::{collections::HashSet, fs, path::Path, time::SystemTime};
+
+use anyhow::{Context, Result};
+use lopdf::{decode_text_string, dictionary, text_string, Dictionary, Document, Object, Stream, StringFormat};
+use md5::{Digest, Md5};
+use serde::Serialize;
+
+use super::metadata::Timestamp;
+
+/// Name trees deeper than this are taken to be broken.
+const MAX_TREE_DEPTH: usize = 32;
+
+/// A file embedded in a document through its `EmbeddedFiles` name tree.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Attachment {
+    /// Key of the file in the name tree.
+    pub name: String,
+    /// File name given by the file specification, the key when it has none.
+    pub file_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// Modification date as a PDF date string, e.g. `D:20240131120000Z`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+    pub size: usize,
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+fn resolve<'a>(document: &'a Document, object: &'a Object) -> Option<&'a Object> {
+    document.dereference(object).ok().map(|(_, object)| object)
+}
+
+fn collect_name_tree(document: &Document, node: &Dictionary, depth: usize, entries: &mut Vec<(Vec<u8>, Object)>) {
+    if depth > MAX_TREE_DEPTH {
+        return;
+    }
+    if let Some(names) = node.get(b"Names").ok().and_then(|names| resolve(document, names)).and_then(|names| names.as_array().ok()) {
+        for pair in names.chunks_exact(2) {
+            if let Ok(key) = pair[0].as_str() {
+                entries.push((key.to_vec(), pair[1].clone()));
+            }
+        }
+    }
+    if let Some(kids) = node.get(b"Kids").ok().and_then(|kids| resolve(document, kids)).and_then(|kids| kids.as_array().ok()) {
+        for kid in kids {
+            if let Some(kid) = resolve(document, kid).and_then(|kid| kid.as_dict().ok()) {
+                collect_name_tree(document, kid, depth + 1, entries);
+            }
+        }
+    }
+}
+
+/// The keys and file specifications of the document's `EmbeddedFiles` name tree, in
+/// tree order.
+pub fn embedded_files(document: &Document) -> Vec<(Vec<u8>, Object)> {
+    let mut entries = Vec::new();
+    let tree = document
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Names").ok())
+        .and_then(|names| resolve(document, names))
+        .and_then(|names| names.as_dict().ok())
+        .and_then(|names| names.get(b"EmbeddedFiles").ok())
+        .and_then(|tree| resolve(document, tree))
+        .and_then(|tree| tree.as_dict().ok());
+    if let Some(tree) = tree {
+        collect_name_tree(document, tree, 0, &mut entries);
+    }
+    entries
+}
+
+/// The name tree key `key` as text.
+pub fn attachment_name(key: &[u8]) -> String {
+    decode_text_string(&Object::string_literal(key.to_vec())).unwrap_or_else(|_| String::from_utf8_lossy(key).to_string())
+}
+
+/// Replaces the `EmbeddedFiles` name tree of `catalog` with a flat one holding `entries`,
+/// keeping the other entries of its name dictionary. Keys used more than once get a
+/// number, `report.pdf (2)`, so no file hides another.
+pub fn set_embedded_files(document: &Document, catalog: &mut Dictionary, entries: Vec<(Vec<u8>, Object)>) {
+    let mut names = catalog
+        .get(b"Names")
+        .ok()
+        .and_then(|names| resolve(document, names))
+        .and_then(|names| names.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    if entries.is_empty() {
+        names.remove(b"EmbeddedFiles");
+    } else {
+        let mut used = HashSet::new();
+        let mut entries = entries
+            .into_iter()
+            .map(|(key, file)| {
+                if used.insert(key.clone()) {
+                    return (key, file);
+                }
+                let name = attachment_name(&key);
+                let key = (2..)
+                    .map(|number| text_string(&format!("{} ({})", name, number)).as_str().unwrap().to_vec())
+                    .find(|key| !used.contains(key))
+                    .unwrap();
+                used.insert(key.clone());
+                (key, file)
+            })
+            .collect::<Vec<_>>();
+        // Name tree keys are sorted by their bytes.
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let tree = entries
+            .into_iter()
+            .flat_map(|(key, file)| [Object::string_literal(key), file])
+            .collect::<Vec<_>>();
+        names.set("EmbeddedFiles", dictionary! { "Names" => tree });
+    }
+    if names.is_empty() {
+        catalog.remove(b"Names");
+    } else {
+        catalog.set("Names", names);
+    }
+}
+
+fn text(document: &Document, dictionary: &Dictionary, key: &[u8]) -> Option<String> {
+    dictionary
+        .get(key)
+        .ok()
+        .and_then(|value| resolve(document, value))
+        .and_then(|value| decode_text_string(value).ok())
+}
+
+/// Reads the file specification `file` of the name tree entry `key`, decoding its contents.
+pub fn read_attachment(document: &Document, key: &[u8], file: &Object) -> Result<Attachment> {
+    let name = attachment_name(key);
+    let file = resolve(document, file)
+        .and_then(|file| file.as_dict().ok())
+        .with_context(|| format!("Attachment {} has no file specification", name))?;
+    let stream = file
+        .get(b"EF")
+        .ok()
+        .and_then(|ef| resolve(document, ef))
+        .and_then(|ef| ef.as_dict().ok())
+        .and_then(|ef| ef.get(b"UF").or_else(|_| ef.get(b"F")).ok())
+        .and_then(|stream| resolve(document, stream))
+        .and_then(|stream| stream.as_stream().ok())
+        .with_context(|| format!("Attachment {} has no embedded file stream", name))?;
+    let data = if stream.dict.has(b"Filter") {
+        stream
+            .decompressed_content()
+            .with_context(|| format!("Cannot decode attachment {}", name))?
+    } else {
+        stream.content.clone()
+    };
+    let modified = stream
+        .dict
+        .get(b"Params")
+        .ok()
+        .and_then(|params| resolve(document, params))
+        .and_then(|params| params.as_dict().ok())
+        .and_then(|params| text(document, params, b"ModDate"));
+    Ok(Attachment {
+        file_name: text(document, file, b"UF")
+            .or_else(|| text(document, file, b"F"))
+            .unwrap_or_else(|| name.clone()),
+        description: text(document, file, b"Desc"),
+        mime_type: stream
+            .dict
+            .get(b"Subtype")
+            .and_then(Object::as_name)
+            .ok()
+            .map(|subtype| String::from_utf8_lossy(subtype).to_string()),
+        modified,
+        size: data.len(),
+        data,
+        name,
+    })
+}
+
+/// Reads every file of the document's `EmbeddedFiles` name tree, failing on the first
+/// that cannot be read.
+pub fn list_attachments(document: &Document) -> Result<Vec<Attachment>> {
+    embedded_files(document)
+        .into_iter()
+        .map(|(key, file)| read_attachment(document, &key, &file))
+        .collect()
+}
+
+/// An input file as it was read, to be embedded in the output.
+#[derive(Debug, Clone)]
+pub struct OriginalFile {
+    pub file_name: String,
+    pub modified: Option<SystemTime>,
+    pub data: Vec<u8>,
+}
+
+impl OriginalFile {
+    pub fn read(path: &Path) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("Cannot read {}", path.display()))?;
+        Ok(OriginalFile {
+            file_name: path
+                .file_name()
+                .map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy())
+                .to_string(),
+            modified: fs::metadata(path).and_then(|metadata| metadata.modified()).ok(),
+            data,
+        })
+    }
+}
+
+/// Media type of the inputs stapler reads, by file extension.
+fn mime_type(file_name: &str) -> Option<&'static str> {
+    let (_, extension) = file_name.rsplit_once('.')?;
+    match extension.to_lowercase().as_str() {
+        "pdf" => Some("application/pdf"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "png" => Some("image/png"),
+        "tif" | "tiff" => Some("image/tiff"),
+        "txt" => Some("text/plain"),
+        _ => None,
+    }
+}
+
+/// Adds `files` to `document` as embedded file streams carrying their size, modification
+/// date and MD5 checksum, and returns their name tree entries keyed by file name.
+pub fn embed_original_files(document: &mut Document, files: Vec<OriginalFile>) -> Vec<(Vec<u8>, Object)> {
+    files
+        .into_iter()
+        .map(|file| {
+            let mut params = dictionary! {
+                "Size" => file.data.len() as i64,
+                "CheckSum" => Object::String(Md5::digest(&file.data).to_vec(), StringFormat::Hexadecimal),
+            };
+            if let Some(modified) = file.modified {
+                params.set("ModDate", Object::string_literal(Timestamp::from_system_time(modified).to_pdf_date()));
+            }
+            let mut stream_dict = dictionary! {
+                "Type" => "EmbeddedFile",
+                "Params" => params,
+            };
+            if let Some(mime_type) = mime_type(&file.file_name) {
+                stream_dict.set("Subtype", Object::Name(mime_type.as_bytes().to_vec()));
+            }
+            let stream_id = document.add_object(Stream::new(stream_dict, file.data));
+            let file_id = document.add_object(dictionary! {
+                "Type" => "Filespec",
+                "F" => text_string(&file.file_name),
+                "UF" => text_string(&file.file_name),
+                "Desc" => text_string("Original input file"),
+                "AFRelationship" => "Source",
+                "EF" => dictionary! { "F" => stream_id, "UF" => stream_id },
+            });
+            (text_string(&file.file_name).as_str().unwrap().to_vec(), Object::Reference(file_id))
+        })
+        .collect()
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use lopdf::{content::Content, Document, Object, ObjectId, Stream};
+
+use super::images::{decode_image, page_xobjects, xobjects_of};
+
+const MAX_FORM_DEPTH: usize = 8;
+/// Gray level from which a pixel counts as paper white.
+const WHITE_LEVEL: u8 = 230;
+/// Share of darker pixels a near-white image may have, for dust and scanner noise.
+const MAX_DARK_SHARE: f64 = 0.005;
+
+/// How blank pages are recognized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlankPageOptions {
+    /// Also treat pages that only show near-white images as blank, as scanners produce
+    /// for empty sheets.
+    pub images: bool,
+}
+
+fn is_whitespace(text: &[u8]) -> bool {
+    text.iter().all(|byte| matches!(byte, b' ' | b'\t' | b'\r' | b'\n'))
+}
+
+fn shows_text(operands: &[Object]) -> bool {
+    operands.iter().any(|operand| match operand {
+        Object::String(text, _) => !is_whitespace(text),
+        Object::Array(items) => shows_text(items),
+        _ => false,
+    })
+}
+
+/// Whether a color operator sets white: gray 1, RGB 1 1 1 or CMYK 0 0 0 0.
+fn is_white(operator: &str, operands: &[Object]) -> bool {
+    let values = operands
+        .iter()
+        .map(|operand| operand.as_float().ok())
+        .collect::<Option<Vec<f32>>>()
+        .unwrap_or_default();
+    match operator {
+        "g" | "G" | "rg" | "RG" => !values.is_empty() && values.iter().all(|value| *value >= 1.0),
+        "k" | "K" => values.len() == 4 && values.iter().all(|value| *value <= 0.0),
+        _ => false,
+    }
+}
+
+/// Whether bilevel pixels, packed eight to a byte with padded rows, are all but white.
+fn bilevel_is_near_white(pixels: &[u8], width: u32, height: u32) -> bool {
+    let row_length = width.div_ceil(8) as usize;
+    if pixels.len() < row_length * height as usize {
+        return false;
+    }
+    let dark = pixels
+        .chunks_exact(row_length)
+        .take(height as usize)
+        .map(|row| {
+            (0..width as usize)
+                .filter(|x| row[x / 8] & (0x80 >> (x % 8)) == 0)
+                .count()
+        })
+        .sum::<usize>();
+    dark as f64 <= (width as f64 * height as f64) * MAX_DARK_SHARE
+}
+
+fn is_near_white_image(document: &Document, image: &Stream) -> bool {
+    let dict = &image.dict;
+    let is_bilevel_gray = dict.get(b"BitsPerComponent").and_then(Object::as_i64).ok() == Some(1)
+        && matches!(dict.get(b"ColorSpace").and_then(Object::as_name), Ok(b"DeviceGray"))
+        && !dict.has(b"Decode");
+    if is_bilevel_gray {
+        let size = |key: &[u8]| dict.get(key).and_then(Object::as_i64).ok().and_then(|size| u32::try_from(size).ok());
+        return match (size(b"Width"), size(b"Height"), image.get_plain_content()) {
+            (Some(width), Some(height), Ok(pixels)) => bilevel_is_near_white(&pixels, width, height),
+            _ => false,
+        };
+    }
+    let Some(pixels) = decode_image(document, image) else {
+        return false;
+    };
+    let pixels = pixels.to_luma8();
+    let dark = pixels.pixels().filter(|pixel| pixel.0[0] < WHITE_LEVEL).count();
+    dark as f64 <= pixels.len() as f64 * MAX_DARK_SHARE
+}
+
+/// Whether `content` leaves the page white: it shows no text but whitespace, paints
+/// nothing but white and, with `options.images`, draws only near-white images.
+fn content_is_blank(
+    document: &Document,
+    content: &[u8],
+    xobjects: &HashMap<Vec<u8>, ObjectId>,
+    options: &BlankPageOptions,
+    depth: usize,
+) -> bool {
+    let Ok(content) = Content::decode(content) else {
+        return false;
+    };
+    // Fill and stroke start out black.
+    let (mut fill_white, mut stroke_white) = (false, false);
+    let mut saved_states = Vec::new();
+
+    for operation in content.operations {
+        let operands = &operation.operands;
+        let paints = match operation.operator.as_str() {
+            "q" => {
+                saved_states.push((fill_white, stroke_white));
+                false
+            }
+            "Q" => {
+                (fill_white, stroke_white) = saved_states.pop().unwrap_or((fill_white, stroke_white));
+                false
+            }
+            operator @ ("g" | "rg" | "k") => {
+                fill_white = is_white(operator, operands);
+                false
+            }
+            operator @ ("G" | "RG" | "K") => {
+                stroke_white = is_white(operator, operands);
+                false
+            }
+            // Colors in other color spaces are not worth telling apart.
+            "cs" | "sc" | "scn" => {
+                fill_white = false;
+                false
+            }
+            "CS" | "SC" | "SCN" => {
+                stroke_white = false;
+                false
+            }
+            "Tj" | "TJ" | "'" | "\"" => shows_text(operands),
+            "f" | "F" | "f*" => !fill_white,
+            "S" | "s" => !stroke_white,
+            "B" | "B*" | "b" | "b*" => !(fill_white && stroke_white),
+            "sh" | "BI" | "EI" => true,
+            "Do" => {
+                let Some(id) = operands
+                    .first()
+                    .and_then(|name| name.as_name().ok())
+                    .and_then(|name| xobjects.get(name))
+                else {
+                    continue;
+                };
+                let Ok(stream) = document.get_object(*id).and_then(Object::as_stream) else {
+                    continue;
+                };
+                match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+                    Ok(b"Image") => !(options.images && is_near_white_image(document, stream)),
+                    Ok(b"Form") if depth < MAX_FORM_DEPTH => {
+                        let mut form_xobjects = HashMap::new();
+                        if let Ok(resources) = stream
+                            .dict
+                            .get(b"Resources")
+                            .and_then(|resources| document.dereference(resources))
+                            .and_then(|(_, resources)| resources.as_dict())
+                        {
+                            xobjects_of(document, resources, &mut form_xobjects);
+                        }
+                        for (name, id) in xobjects {
+                            form_xobjects.entry(name.clone()).or_insert(*id);
+                        }
+                        let form_content = stream
+                            .decompressed_content()
+                            .unwrap_or_else(|_| stream.content.clone());
+                        !content_is_blank(document, &form_content, &form_xobjects, options, depth + 1)
+                    }
+                    _ => true,
+                }
+            }
+            _ => false,
+        };
+        if paints {
+            return false;
+        }
+    }
+    true
+}
+
+/// Whether the page shows nothing: its content leaves it white and it carries no
+/// annotations other than links.
+pub fn is_blank_page(document: &Document, page_id: ObjectId, options: &BlankPageOptions) -> bool {
+    let has_annotations = document
+        .get_page_annotations(page_id)
+        .unwrap_or_default()
+        .iter()
+        .any(|annotation| !matches!(annotation.get(b"Subtype").and_then(Object::as_name), Ok(b"Link" | b"Popup")));
+    if has_annotations {
+        return false;
+    }
+    let Ok(content) = document.get_page_content(page_id) else {
+        return false;
+    };
+    content_is_blank(document, &content, &page_xobjects(document, page_id), options, 0)
+}
@@ -0,0 +1,136 @@
+use std::io::Write;
+
+use clap::{builder::PossibleValue, ValueEnum};
+use flate2::{write::ZlibEncoder, Compression};
+use lopdf::{Document, Object, Stream};
+use serde::Deserialize;
+
+/// How hard Flate works when compressing streams.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionLevel {
+    Fast,
+    #[default]
+    Default,
+    Max,
+}
+
+impl CompressionLevel {
+    fn flate(self) -> Compression {
+        match self {
+            CompressionLevel::Fast => Compression::fast(),
+            CompressionLevel::Default => Compression::default(),
+            CompressionLevel::Max => Compression::best(),
+        }
+    }
+}
+
+/// The command line names of the levels, the same as in manifests.
+impl ValueEnum for CompressionLevel {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[CompressionLevel::Fast, CompressionLevel::Default, CompressionLevel::Max]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(PossibleValue::new(match self {
+            CompressionLevel::Fast => "fast",
+            CompressionLevel::Default => "default",
+            CompressionLevel::Max => "max",
+        }))
+    }
+}
+
+/// What happens to the streams of the merged document before it is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StreamCompression {
+    /// Streams are written the way the inputs stored them.
+    #[default]
+    Unchanged,
+    /// Uncompressed streams are Flate encoded and existing Flate streams re-encoded.
+    Compress(CompressionLevel),
+    /// Every stream that can be decoded is written uncompressed, mostly for debugging.
+    Decompress,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionOptions {
+    pub streams: StreamCompression,
+    /// Leave image streams that already carry a filter untouched.
+    pub keep_compressed_images: bool,
+}
+
+impl CompressionOptions {
+    pub fn compressed(level: CompressionLevel) -> Self {
+        CompressionOptions {
+            streams: StreamCompression::Compress(level),
+            ..Default::default()
+        }
+    }
+}
+
+pub(crate) fn deflate(data: &[u8], level: CompressionLevel) -> Option<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), level.flate());
+    encoder.write_all(data).ok()?;
+    encoder.finish().ok()
+}
+
+fn is_compressed_image(stream: &Stream) -> bool {
+    stream.is_compressed()
+        && matches!(
+            stream.dict.get(b"Subtype").and_then(Object::as_name),
+            Ok(b"Image")
+        )
+}
+
+fn compress_stream(stream: &mut Stream, level: CompressionLevel) {
+    let plain_content = match stream.filters() {
+        // Only a missing filter means plain data; indirect or malformed ones are left alone.
+        Err(_) if !stream.dict.has(b"Filter") => stream.content.clone(),
+        Err(_) => return,
+        Ok(filters) if filters.is_empty() => stream.content.clone(),
+        // Predictors are left alone, re-encoding without them rarely pays off.
+        Ok(filters)
+            if matches!(filters.as_slice(), [b"FlateDecode"]) && !stream.dict.has(b"DecodeParms") =>
+        {
+            match stream.decompressed_content() {
+                Ok(content) => content,
+                Err(_) => return,
+            }
+        }
+        Ok(_) => return,
+    };
+
+    if let Some(compressed) = deflate(&plain_content, level) {
+        if compressed.len() < stream.content.len() {
+            stream.dict.set("Filter", "FlateDecode");
+            stream.set_content(compressed);
+        }
+    }
+}
+
+/// Compresses or decompresses the streams of `document` as `options` asks for.
+///
+/// Only Flate is ever written. Streams using other filters (JPEG, JBIG2, ...) and
+/// streams that opted out of compression are kept as they are.
+pub fn apply_compression(document: &mut Document, options: &CompressionOptions) {
+    for object in document.objects.values_mut() {
+        let Object::Stream(stream) = object else {
+            continue;
+        };
+        if options.keep_compressed_images && is_compressed_image(stream) {
+            continue;
+        }
+        match options.streams {
+            StreamCompression::Unchanged => {}
+            StreamCompression::Compress(level) => {
+                if stream.allows_compression {
+                    compress_stream(stream, level);
+                }
+            }
+            StreamCompression::Decompress => {
+                // Streams using filters lopdf cannot decode stay compressed.
+                let _ = stream.decompress();
+            }
+        }
+    }
+}
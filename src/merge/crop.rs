@@ -0,0 +1,228 @@
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use lopdf::{Document, Object, ObjectId};
+use serde::Deserialize;
+
+use super::loader::{get_inherited_attribute, get_page_box};
+use super::page_size::{format_number, parse_length, split_unit, wrap_page_contents};
+
+/// What is cut away from the pages of an input, in the page's own coordinates before
+/// any rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum PageCrop {
+    /// Keep `[left, bottom, right, top]` in points, measured from the lower left corner
+    /// of the page.
+    Box([f32; 4]),
+    /// Cut `[top, right, bottom, left]` percent of the page's height and width.
+    Margins([f32; 4]),
+}
+
+impl PageCrop {
+    /// The box left of `page_box` after cropping, `None` if nothing is left.
+    fn apply(&self, [left, bottom, right, top]: [f32; 4]) -> Option<[f32; 4]> {
+        let cropped = match *self {
+            PageCrop::Box([x1, y1, x2, y2]) => [
+                left + x1,
+                bottom + y1,
+                right.min(left + x2),
+                top.min(bottom + y2),
+            ],
+            PageCrop::Margins([cut_top, cut_right, cut_bottom, cut_left]) => {
+                let (width, height) = ((right - left) / 100.0, (top - bottom) / 100.0);
+                [
+                    left + cut_left * width,
+                    bottom + cut_bottom * height,
+                    right - cut_right * width,
+                    top - cut_top * height,
+                ]
+            }
+        };
+        let [x1, y1, x2, y2] = cropped;
+        (x1 < x2 && y1 < y2).then_some(cropped)
+    }
+}
+
+impl FromStr for PageCrop {
+    type Err = anyhow::Error;
+
+    /// Parses percentages like CSS margins (`5%`, `5%,10%` or `TOP,RIGHT,BOTTOM,LEFT`),
+    /// or a box `LEFT,BOTTOM,RIGHT,TOP` with an optional unit at the end, e.g.
+    /// `10,10,200,287mm`.
+    fn from_str(crop: &str) -> Result<Self> {
+        let crop = crop.trim().to_lowercase();
+        if crop.contains('%') {
+            let margins = crop
+                .split(',')
+                .map(|margin| {
+                    let percent = margin
+                        .trim()
+                        .strip_suffix('%')
+                        .and_then(|margin| margin.trim().parse::<f32>().ok())
+                        .with_context(|| format!("'{}' is not a percentage", margin.trim()))?;
+                    anyhow::ensure!(
+                        (0.0..100.0).contains(&percent),
+                        "crop margins must be at least 0% and below 100%"
+                    );
+                    Ok(percent)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let margins = match *margins.as_slice() {
+                [all] => [all; 4],
+                [vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+                [top, right, bottom, left] => [top, right, bottom, left],
+                _ => anyhow::bail!("crop margins take one, two or four percentages"),
+            };
+            let [top, right, bottom, left] = margins;
+            anyhow::ensure!(
+                top + bottom < 100.0 && left + right < 100.0,
+                "crop margins leave nothing of the page"
+            );
+            return Ok(PageCrop::Margins(margins));
+        }
+
+        let (values, unit) = split_unit(&crop);
+        let values = values
+            .split(',')
+            .map(|value| {
+                value
+                    .trim()
+                    .parse::<f32>()
+                    .map(|value| value * unit)
+                    .with_context(|| format!("'{}' is not a coordinate", value.trim()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let &[left, bottom, right, top] = values.as_slice() else {
+            anyhow::bail!(
+                "'{}' is not a crop, expected percentages such as 5% or a box LEFT,BOTTOM,RIGHT,TOP[pt|mm|in]",
+                crop
+            );
+        };
+        anyhow::ensure!(
+            left >= 0.0 && bottom >= 0.0 && left < right && bottom < top,
+            "a crop box needs LEFT < RIGHT and BOTTOM < TOP, starting at 0 or above"
+        );
+        Ok(PageCrop::Box([left, bottom, right, top]))
+    }
+}
+
+impl TryFrom<String> for PageCrop {
+    type Error = String;
+
+    fn try_from(crop: String) -> std::result::Result<Self, Self::Error> {
+        crop.parse().map_err(|error| format!("{:#}", error))
+    }
+}
+
+/// Sets the crop box of every page, failing on pages the crop leaves nothing of.
+pub fn crop_pages(document: &mut Document, crop: &PageCrop) -> Result<()> {
+    for (page_number, page_id) in document.get_pages() {
+        let page_box = get_page_box(document, page_id).context("page has no media box")?;
+        let [left, bottom, right, top] = crop
+            .apply(page_box)
+            .with_context(|| format!("The crop leaves nothing of page {}", page_number))?;
+        document
+            .get_dictionary_mut(page_id)?
+            .set("CropBox", vec![left.into(), bottom.into(), right.into(), top.into()]);
+    }
+    Ok(())
+}
+
+/// A length in points, written as `12`, `12pt`, `5mm` or `0.5in`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "LengthValue")]
+pub struct Length(pub f32);
+
+impl FromStr for Length {
+    type Err = anyhow::Error;
+
+    fn from_str(length: &str) -> Result<Self> {
+        parse_length(length).map(Length)
+    }
+}
+
+/// Manifests may give lengths in points as plain numbers.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LengthValue {
+    Text(String),
+    Number(f32),
+}
+
+impl TryFrom<LengthValue> for Length {
+    type Error = anyhow::Error;
+
+    fn try_from(length: LengthValue) -> Result<Self> {
+        match length {
+            LengthValue::Text(length) => length.parse(),
+            LengthValue::Number(points) => {
+                anyhow::ensure!(points >= 0.0, "lengths must not be negative");
+                Ok(Length(points))
+            }
+        }
+    }
+}
+
+/// White space added around every page of the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PageMargins {
+    /// Added on all four sides, in points.
+    pub margin: f32,
+    /// Added on the binding side on top of `margin`: left on odd pages, right on even
+    /// pages, as they are displayed.
+    pub gutter: f32,
+}
+
+fn add_page_margins(document: &mut Document, page_id: ObjectId, page_number: u32, margins: &PageMargins) {
+    let Some([left, bottom, right, top]) = get_page_box(document, page_id) else {
+        return;
+    };
+    let rotation = get_inherited_attribute(document, page_id, b"Rotate")
+        .and_then(|rotate| rotate.as_i64().ok())
+        .unwrap_or(0)
+        .rem_euclid(360);
+    // Extra space per side as [left, bottom, right, top] of the unrotated page. Turning
+    // the page clockwise brings its bottom edge to the left.
+    let mut extra = [margins.margin; 4];
+    let displayed_side = if page_number % 2 == 1 { 0 } else { 2 };
+    let side = match rotation {
+        90 => displayed_side + 1,
+        180 => displayed_side + 2,
+        270 => displayed_side + 3,
+        _ => displayed_side,
+    } % 4;
+    extra[side] += margins.gutter;
+
+    // The clip keeps content outside the old crop box out of the new margins.
+    let prefix = format!(
+        "q {} {} {} {} re W n\n",
+        format_number(left),
+        format_number(bottom),
+        format_number(right - left),
+        format_number(top - bottom),
+    );
+    wrap_page_contents(document, page_id, prefix);
+    let Ok(page) = document.get_dictionary_mut(page_id) else {
+        return;
+    };
+    page.set(
+        "MediaBox",
+        vec![
+            Object::Real(left - extra[0]),
+            Object::Real(bottom - extra[1]),
+            Object::Real(right + extra[2]),
+            Object::Real(top + extra[3]),
+        ],
+    );
+    for key in [b"CropBox".as_slice(), b"BleedBox", b"TrimBox", b"ArtBox"] {
+        page.remove(key);
+    }
+}
+
+/// Grows every page by `margins`, leaving its content where it is.
+pub fn add_margins(document: &mut Document, margins: &PageMargins) {
+    for (page_number, page_id) in document.get_pages() {
+        add_page_margins(document, page_id, page_number, margins);
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use lopdf::{Dictionary, Document, Object, ObjectId};
+
+use super::writer::RemovalSummary;
+
+fn hash_dictionary(dictionary: &Dictionary, state: &mut DefaultHasher) {
+    // Dictionaries compare equal regardless of key order, so hash them in a stable order.
+    let mut entries = dictionary.iter().collect::<Vec<_>>();
+    entries.sort_by_key(|(key, _)| *key);
+    entries.len().hash(state);
+    for (key, value) in entries {
+        key.hash(state);
+        hash_object(value, state);
+    }
+}
+
+fn hash_object(object: &Object, state: &mut DefaultHasher) {
+    std::mem::discriminant(object).hash(state);
+    match object {
+        Object::Null => {}
+        Object::Boolean(value) => value.hash(state),
+        Object::Integer(value) => value.hash(state),
+        Object::Real(value) => value.to_bits().hash(state),
+        Object::Name(name) => name.hash(state),
+        Object::String(text, _) => text.hash(state),
+        Object::Array(array) => {
+            array.len().hash(state);
+            array.iter().for_each(|item| hash_object(item, state));
+        }
+        Object::Dictionary(dictionary) => hash_dictionary(dictionary, state),
+        Object::Stream(stream) => {
+            hash_dictionary(&stream.dict, state);
+            stream.content.hash(state);
+        }
+        Object::Reference(id) => id.hash(state),
+    }
+}
+
+fn same_object(left: &Object, right: &Object) -> bool {
+    match (left, right) {
+        // The position a stream was read from must not keep identical streams apart.
+        (Object::Stream(left), Object::Stream(right)) => {
+            left.dict == right.dict && left.content == right.content
+        }
+        _ => left == right,
+    }
+}
+
+/// Pages, annotations, form fields, signatures and the document structure must stay
+/// distinct even when they look alike.
+fn is_deduplicable(object: &Object) -> bool {
+    let dictionary = match object {
+        Object::Dictionary(dictionary) => dictionary,
+        Object::Stream(stream) => &stream.dict,
+        Object::Array(_) => return true,
+        _ => return false,
+    };
+    if matches!(
+        object.type_name().unwrap_or(b""),
+        b"Catalog" | b"Pages" | b"Page" | b"Outlines" | b"ObjStm" | b"XRef" | b"Annot" | b"Sig"
+    ) {
+        return false;
+    }
+    // `Type` is optional for these, so they are told apart by their required entries.
+    let is_annotation = dictionary.has(b"Subtype") && dictionary.has(b"Rect");
+    let is_field = dictionary.has(b"FT") || (dictionary.has(b"T") && (dictionary.has(b"Parent") || dictionary.has(b"Kids")));
+    let is_signature = dictionary.has(b"ByteRange");
+    !(is_annotation || is_field || is_signature)
+}
+
+pub(crate) fn replace_references(object: &mut Object, replacements: &BTreeMap<ObjectId, ObjectId>) {
+    match object {
+        Object::Reference(id) => {
+            if let Some(replacement) = replacements.get(id) {
+                *id = *replacement;
+            }
+        }
+        Object::Array(array) => array
+            .iter_mut()
+            .for_each(|item| replace_references(item, replacements)),
+        Object::Dictionary(dictionary) => dictionary
+            .iter_mut()
+            .for_each(|(_, value)| replace_references(value, replacements)),
+        Object::Stream(stream) => stream
+            .dict
+            .iter_mut()
+            .for_each(|(_, value)| replace_references(value, replacements)),
+        _ => {}
+    }
+}
+
+fn find_duplicates(document: &Document) -> BTreeMap<ObjectId, ObjectId> {
+    let mut candidates: HashMap<u64, Vec<ObjectId>> = HashMap::new();
+    for (id, object) in &document.objects {
+        if is_deduplicable(object) {
+            let mut state = DefaultHasher::new();
+            hash_object(object, &mut state);
+            candidates.entry(state.finish()).or_default().push(*id);
+        }
+    }
+
+    let mut duplicates = BTreeMap::new();
+    for ids in candidates.values().filter(|ids| ids.len() > 1) {
+        let mut originals: Vec<ObjectId> = Vec::new();
+        for id in ids {
+            let object = &document.objects[id];
+            match originals
+                .iter()
+                .find(|original| same_object(&document.objects[original], object))
+            {
+                Some(original) => {
+                    duplicates.insert(*id, *original);
+                }
+                None => originals.push(*id),
+            }
+        }
+    }
+    duplicates
+}
+
+/// Collapses identical objects (fonts, images, ICC profiles, ...) into a single copy.
+///
+/// Runs until no duplicates remain, since merging e.g. two font files makes the font
+/// descriptors referencing them identical in the next round.
+pub fn deduplicate_objects(document: &mut Document) -> RemovalSummary {
+    let mut summary = RemovalSummary::default();
+
+    loop {
+        let duplicates = find_duplicates(document);
+        if duplicates.is_empty() {
+            break;
+        }
+
+        for id in duplicates.keys() {
+            if let Some(object) = document.objects.remove(id) {
+                summary.record(*id, &object);
+            }
+        }
+        for object in document.objects.values_mut() {
+            replace_references(object, &duplicates);
+        }
+        for (_, value) in document.trailer.iter_mut() {
+            replace_references(value, &duplicates);
+        }
+    }
+
+    if summary.objects_removed > 0 {
+        document.renumber_objects();
+    }
+
+    summary
+}
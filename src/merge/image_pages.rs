@@ -0,0 +1,320 @@
+use std::{fs, io::Cursor, path::Path};
+
+use anyhow::{Context, Result};
+use image::{metadata::Orientation, DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use lopdf::{dictionary, xref::XrefType, Dictionary, Document, Object, Stream};
+use serde::Deserialize;
+use tiff::{
+    decoder::{Decoder, DecodingResult},
+    ColorType,
+};
+
+use super::compression::{deflate, CompressionLevel};
+use super::page_size::{format_number, PageSize, POINTS_PER_INCH};
+
+fn default_dpi() -> u32 {
+    300
+}
+
+/// How image inputs are turned into pages.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImagePageOptions {
+    /// Resolution the images are taken to have, which gives the page size.
+    #[serde(default = "default_dpi")]
+    pub dpi: u32,
+    /// Fit every image onto pages of this size instead, turned to the image's orientation.
+    pub page_size: Option<PageSize>,
+}
+
+impl Default for ImagePageOptions {
+    fn default() -> Self {
+        ImagePageOptions {
+            dpi: default_dpi(),
+            page_size: None,
+        }
+    }
+}
+
+/// Pixels ready to be written as an image XObject.
+struct PageImage {
+    width: u32,
+    height: u32,
+    color_space: &'static str,
+    bits_per_component: u8,
+    /// Encoded pixels, with the filter that decodes them.
+    data: Vec<u8>,
+    filter: &'static str,
+    /// 8-bit alpha channel, written as a soft mask.
+    alpha: Option<Vec<u8>>,
+    /// How the pixels are turned to be shown upright, from the EXIF data.
+    orientation: Orientation,
+}
+
+impl PageImage {
+    fn flate(
+        width: u32,
+        height: u32,
+        color_space: &'static str,
+        bits_per_component: u8,
+        pixels: &[u8],
+    ) -> Result<Self> {
+        Ok(PageImage {
+            width,
+            height,
+            color_space,
+            bits_per_component,
+            data: deflate(pixels, CompressionLevel::Default).context("Cannot compress image")?,
+            filter: "FlateDecode",
+            alpha: None,
+            orientation: Orientation::NoTransforms,
+        })
+    }
+
+    fn from_image(image: DynamicImage, orientation: Orientation) -> Result<Self> {
+        let (width, height) = (image.width(), image.height());
+        let grayscale = matches!(
+            image.color(),
+            image::ColorType::L8 | image::ColorType::La8 | image::ColorType::L16 | image::ColorType::La16
+        );
+        let alpha = image
+            .color()
+            .has_alpha()
+            .then(|| image.to_luma_alpha8().pixels().map(|pixel| pixel.0[1]).collect::<Vec<_>>())
+            .filter(|alpha| alpha.iter().any(|value| *value != u8::MAX));
+        let mut page_image = if grayscale {
+            PageImage::flate(width, height, "DeviceGray", 8, image.to_luma8().as_raw())?
+        } else {
+            PageImage::flate(width, height, "DeviceRGB", 8, image.to_rgb8().as_raw())?
+        };
+        page_image.alpha = alpha;
+        page_image.orientation = orientation;
+        Ok(page_image)
+    }
+
+    /// Width and height of the image once turned upright.
+    fn shown_size(&self) -> (u32, u32) {
+        match self.orientation {
+            Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH => {
+                (self.height, self.width)
+            }
+            _ => (self.width, self.height),
+        }
+    }
+
+    fn into_xobject(self, document: &mut Document) -> Result<Object> {
+        let mut dict = dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Image",
+            "Width" => self.width,
+            "Height" => self.height,
+            "ColorSpace" => self.color_space,
+            "BitsPerComponent" => self.bits_per_component,
+            "Filter" => self.filter,
+        };
+        if let Some(alpha) = self.alpha {
+            let mask = PageImage::flate(self.width, self.height, "DeviceGray", 8, &alpha)?;
+            dict.set("SMask", mask.into_xobject(document)?);
+        }
+        let image = Stream::new(dict, self.data).with_compression(false);
+        Ok(Object::Reference(document.add_object(image)))
+    }
+}
+
+/// Number of color components in the frame header of a JPEG file.
+fn jpeg_components(data: &[u8]) -> Option<u8> {
+    let mut position = 2;
+    while position + 4 <= data.len() {
+        if data[position] != 0xFF {
+            return None;
+        }
+        let marker = data[position + 1];
+        let length = u16::from_be_bytes([data[position + 2], data[position + 3]]) as usize;
+        // Start of frame markers, leaving out DHT (C4), JPG (C8) and DAC (CC).
+        if matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            return data.get(position + 9).copied();
+        }
+        position += 2 + length;
+    }
+    None
+}
+
+/// Decodes an image along with the orientation its EXIF data asks for.
+fn decode_image(data: &[u8], format: ImageFormat) -> Result<(DynamicImage, Orientation)> {
+    let mut decoder = ImageReader::with_format(Cursor::new(data), format).into_decoder()?;
+    let orientation = decoder.orientation()?;
+    Ok((DynamicImage::from_decoder(decoder)?, orientation))
+}
+
+fn jpeg_image(data: Vec<u8>) -> Result<PageImage> {
+    let (image, orientation) = decode_image(&data, ImageFormat::Jpeg).context("Cannot decode JPEG image")?;
+    // Gray and RGB JPEGs are embedded as they are, without losing quality to re-encoding.
+    let color_space = match jpeg_components(&data) {
+        Some(1) => "DeviceGray",
+        Some(3) => "DeviceRGB",
+        _ => return PageImage::from_image(image, orientation),
+    };
+    Ok(PageImage {
+        width: image.width(),
+        height: image.height(),
+        color_space,
+        bits_per_component: 8,
+        data,
+        filter: "DCTDecode",
+        alpha: None,
+        orientation,
+    })
+}
+
+fn tiff_frame(decoder: &mut Decoder<Cursor<Vec<u8>>>) -> Result<PageImage> {
+    let (width, height) = decoder.dimensions()?;
+    let color_type = decoder.colortype()?;
+    let pixels = decoder.read_image()?;
+    let unsupported = || anyhow::anyhow!("Unsupported TIFF color type {:?}", color_type);
+    let image = match (color_type, pixels) {
+        // Bilevel scans stay one bit per pixel, rows padded to whole bytes.
+        (ColorType::Gray(1), DecodingResult::U8(pixels)) => {
+            return PageImage::flate(width, height, "DeviceGray", 1, &pixels);
+        }
+        (ColorType::Gray(8), DecodingResult::U8(pixels)) => {
+            image::GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8)
+        }
+        (ColorType::GrayA(8), DecodingResult::U8(pixels)) => {
+            image::GrayAlphaImage::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8)
+        }
+        (ColorType::RGB(8), DecodingResult::U8(pixels)) => {
+            image::RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::RGBA(8), DecodingResult::U8(pixels)) => {
+            image::RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8)
+        }
+        (ColorType::CMYK(8), DecodingResult::U8(pixels)) => {
+            let rgb = pixels
+                .chunks_exact(4)
+                .flat_map(|cmyk| {
+                    let black = 255 - u16::from(cmyk[3]);
+                    [0, 1, 2].map(|channel| ((255 - u16::from(cmyk[channel])) * black / 255) as u8)
+                })
+                .collect();
+            image::RgbImage::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::Gray(16), DecodingResult::U16(pixels)) => {
+            image::ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma16)
+        }
+        (ColorType::RGB(16), DecodingResult::U16(pixels)) => {
+            image::ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb16)
+        }
+        (ColorType::RGBA(16), DecodingResult::U16(pixels)) => {
+            image::ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba16)
+        }
+        _ => return Err(unsupported()),
+    };
+    PageImage::from_image(image.ok_or_else(unsupported)?, Orientation::NoTransforms)
+}
+
+/// Every page of a TIFF file, in file order.
+fn tiff_images(data: Vec<u8>) -> Result<Vec<PageImage>> {
+    let mut decoder = Decoder::new(Cursor::new(data)).context("Cannot decode TIFF image")?;
+    let mut images = Vec::new();
+    loop {
+        images.push(tiff_frame(&mut decoder).with_context(|| format!("Cannot decode TIFF page {}", images.len() + 1))?);
+        if !decoder.more_images() {
+            return Ok(images);
+        }
+        decoder.next_image()?;
+    }
+}
+
+/// Size of the page an image is placed on, and where on it the image goes.
+fn page_layout(width: u32, height: u32, options: &ImagePageOptions) -> ([f32; 2], [f32; 4]) {
+    let scale = POINTS_PER_INCH / options.dpi as f32;
+    let (image_width, image_height) = (width as f32 * scale, height as f32 * scale);
+    let Some(page_size) = options.page_size else {
+        return ([image_width, image_height], [image_width, image_height, 0.0, 0.0]);
+    };
+    let (page_width, page_height) = if width > height {
+        (page_size.height, page_size.width)
+    } else {
+        (page_size.width, page_size.height)
+    };
+    let fit = (page_width / image_width).min(page_height / image_height);
+    let (shown_width, shown_height) = (image_width * fit, image_height * fit);
+    (
+        [page_width, page_height],
+        [
+            shown_width,
+            shown_height,
+            (page_width - shown_width) / 2.0,
+            (page_height - shown_height) / 2.0,
+        ],
+    )
+}
+
+/// Matrix drawing the unit square image upright into the `width` by `height` box at `x`, `y`.
+fn image_matrix(orientation: Orientation, [width, height, x, y]: [f32; 4]) -> [f32; 6] {
+    match orientation {
+        Orientation::NoTransforms => [width, 0.0, 0.0, height, x, y],
+        Orientation::Rotate90 => [0.0, -height, width, 0.0, x, y + height],
+        Orientation::Rotate180 => [-width, 0.0, 0.0, -height, x + width, y + height],
+        Orientation::Rotate270 => [0.0, height, -width, 0.0, x + width, y],
+        Orientation::FlipHorizontal => [-width, 0.0, 0.0, height, x + width, y],
+        Orientation::FlipVertical => [width, 0.0, 0.0, -height, x, y + height],
+        Orientation::Rotate90FlipH => [0.0, -height, -width, 0.0, x + width, y + height],
+        Orientation::Rotate270FlipH => [0.0, height, width, 0.0, x, y],
+    }
+}
+
+/// Builds a document with a page per image.
+fn image_document(images: Vec<PageImage>, options: &ImagePageOptions) -> Result<Document> {
+    let mut document = Document::with_version("1.4");
+    document.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+    let pages_id = document.new_object_id();
+    let mut kids = Vec::new();
+    for image in images {
+        let (width, height) = image.shown_size();
+        let ([page_width, page_height], placement) = page_layout(width, height, options);
+        let matrix = image_matrix(image.orientation, placement);
+        let image_id = image.into_xobject(&mut document)?;
+        let matrix = matrix.map(format_number).join(" ");
+        let content = format!("q {} cm /Im0 Do Q", matrix);
+        let content_id = document.add_object(Stream::new(Dictionary::new(), content.into_bytes()));
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), Object::Real(page_width), Object::Real(page_height)],
+            "Contents" => content_id,
+            "Resources" => dictionary! { "XObject" => dictionary! { "Im0" => image_id } },
+        });
+        kids.push(Object::Reference(page_id));
+    }
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as u32,
+            "Kids" => kids,
+        }),
+    );
+    let catalog_id = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    document.trailer.set("Root", catalog_id);
+    Ok(document)
+}
+
+/// Loads a JPEG, PNG or TIFF file as a document with one page per image, every page of
+/// a multi-page TIFF included.
+pub fn load_image_document(path: &Path, format: ImageFormat, options: &ImagePageOptions) -> Result<Document> {
+    anyhow::ensure!(options.dpi > 0, "The image resolution must be greater than 0");
+    let data = fs::read(path)?;
+    let images = match format {
+        ImageFormat::Jpeg => vec![jpeg_image(data)?],
+        ImageFormat::Tiff => tiff_images(data)?,
+        format => {
+            let (image, orientation) = decode_image(&data, format).context("Cannot decode image")?;
+            vec![PageImage::from_image(image, orientation)?]
+        }
+    };
+    image_document(images, options)
+}
@@ -0,0 +1,447 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, GrayImage, ImageFormat, RgbImage};
+use lopdf::{content::Content, Dictionary, Document, Object, ObjectId, Stream};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::compression::{deflate, CompressionLevel};
+
+/// Images with at most this many distinct colors are treated as line art and kept lossless.
+const LINE_ART_MAX_COLORS: usize = 64;
+/// Form XObjects nested deeper than this are not searched for images.
+const MAX_FORM_DEPTH: usize = 8;
+const POINTS_PER_INCH: f32 = 72.0;
+
+fn default_jpeg_quality() -> u8 {
+    75
+}
+
+/// How images are re-encoded after merging.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageOptions {
+    /// Images shown at a higher resolution than this are scaled down to it.
+    pub max_dpi: Option<u32>,
+    /// Quality (1-100) used when re-encoding photographic images as JPEG.
+    #[serde(default = "default_jpeg_quality")]
+    pub jpeg_quality: u8,
+    /// Convert color images to grayscale.
+    #[serde(default)]
+    pub grayscale: bool,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            max_dpi: None,
+            jpeg_quality: default_jpeg_quality(),
+            grayscale: false,
+        }
+    }
+}
+
+impl ImageOptions {
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(
+            (1..=100).contains(&self.jpeg_quality),
+            "jpeg_quality must be between 1 and 100, found {}",
+            self.jpeg_quality
+        );
+        anyhow::ensure!(self.max_dpi != Some(0), "max_dpi must be greater than 0");
+        Ok(())
+    }
+}
+
+/// What the image stage did to the merged document.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ImageSummary {
+    pub images_found: usize,
+    pub images_rewritten: usize,
+    pub images_downsampled: usize,
+    pub bytes_saved: u64,
+}
+
+/// `[a b c d e f]` as used by the `cm` operator.
+pub(crate) type Matrix = [f32; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// `m` followed by `n`, i.e. `m × n` in PDF's row vector convention.
+pub(crate) fn multiply(m: &Matrix, n: &Matrix) -> Matrix {
+    [
+        m[0] * n[0] + m[1] * n[2],
+        m[0] * n[1] + m[1] * n[3],
+        m[2] * n[0] + m[3] * n[2],
+        m[2] * n[1] + m[3] * n[3],
+        m[4] * n[0] + m[5] * n[2] + n[4],
+        m[4] * n[1] + m[5] * n[3] + n[5],
+    ]
+}
+
+fn matrix_from(operands: &[Object]) -> Option<Matrix> {
+    let values = operands
+        .iter()
+        .map(|operand| operand.as_float().ok())
+        .collect::<Option<Vec<f32>>>()?;
+    values.try_into().ok()
+}
+
+pub(crate) fn xobjects_of(document: &Document, resources: &Dictionary, names: &mut HashMap<Vec<u8>, ObjectId>) {
+    let xobjects = resources
+        .get(b"XObject")
+        .and_then(|xobjects| document.dereference(xobjects))
+        .and_then(|(_, xobjects)| xobjects.as_dict());
+    if let Ok(xobjects) = xobjects {
+        for (name, value) in xobjects {
+            if let Ok(id) = value.as_reference() {
+                // Resources closer to the content win over inherited ones.
+                names.entry(name.clone()).or_insert(id);
+            }
+        }
+    }
+}
+
+pub(crate) fn page_xobjects(document: &Document, page_id: ObjectId) -> HashMap<Vec<u8>, ObjectId> {
+    let mut names = HashMap::new();
+    if let Ok((resources, resource_ids)) = document.get_page_resources(page_id) {
+        if let Some(resources) = resources {
+            xobjects_of(document, resources, &mut names);
+        }
+        for id in resource_ids {
+            if let Ok(resources) = document.get_dictionary(id) {
+                xobjects_of(document, resources, &mut names);
+            }
+        }
+    }
+    names
+}
+
+/// Records the largest size (in points) each image is drawn at by `content`.
+fn record_image_usage(
+    document: &Document,
+    content: &[u8],
+    xobjects: &HashMap<Vec<u8>, ObjectId>,
+    ctm: Matrix,
+    depth: usize,
+    usage: &mut HashMap<ObjectId, (f32, f32)>,
+) {
+    let Ok(content) = Content::decode(content) else {
+        return;
+    };
+    let mut ctm = ctm;
+    let mut saved_states = Vec::new();
+
+    for operation in content.operations {
+        match operation.operator.as_str() {
+            "q" => saved_states.push(ctm),
+            "Q" => ctm = saved_states.pop().unwrap_or(ctm),
+            "cm" => {
+                if let Some(matrix) = matrix_from(&operation.operands) {
+                    ctm = multiply(&matrix, &ctm);
+                }
+            }
+            "Do" => {
+                let Some(id) = operation
+                    .operands
+                    .first()
+                    .and_then(|name| name.as_name().ok())
+                    .and_then(|name| xobjects.get(name))
+                else {
+                    continue;
+                };
+                let Ok(stream) = document.get_object(*id).and_then(Object::as_stream) else {
+                    continue;
+                };
+                match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+                    Ok(b"Image") => {
+                        // The image fills the unit square, so the matrix rows give its size.
+                        let width = ctm[0].hypot(ctm[1]);
+                        let height = ctm[2].hypot(ctm[3]);
+                        let size = usage.entry(*id).or_insert((0.0, 0.0));
+                        *size = (size.0.max(width), size.1.max(height));
+                    }
+                    Ok(b"Form") if depth < MAX_FORM_DEPTH => {
+                        let form_matrix = stream
+                            .dict
+                            .get(b"Matrix")
+                            .and_then(Object::as_array)
+                            .ok()
+                            .and_then(|matrix| matrix_from(matrix))
+                            .unwrap_or(IDENTITY);
+                        let mut form_xobjects = HashMap::new();
+                        if let Ok(resources) = stream
+                            .dict
+                            .get(b"Resources")
+                            .and_then(|resources| document.dereference(resources))
+                            .and_then(|(_, resources)| resources.as_dict())
+                        {
+                            xobjects_of(document, resources, &mut form_xobjects);
+                        }
+                        // Forms without resources of their own use the ones of the page.
+                        for (name, id) in xobjects {
+                            form_xobjects.entry(name.clone()).or_insert(*id);
+                        }
+                        let form_content = stream
+                            .decompressed_content()
+                            .unwrap_or_else(|_| stream.content.clone());
+                        record_image_usage(
+                            document,
+                            &form_content,
+                            &form_xobjects,
+                            multiply(&form_matrix, &ctm),
+                            depth + 1,
+                            usage,
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn collect_image_usage(document: &Document) -> HashMap<ObjectId, (f32, f32)> {
+    let mut usage = HashMap::new();
+    for page_id in document.page_iter() {
+        let Ok(content) = document.get_page_content(page_id) else {
+            continue;
+        };
+        let xobjects = page_xobjects(document, page_id);
+        record_image_usage(document, &content, &xobjects, IDENTITY, 0, &mut usage);
+    }
+    usage
+}
+
+fn is_image(object: &Object) -> bool {
+    object
+        .as_stream()
+        .is_ok_and(|stream| matches!(stream.dict.get(b"Subtype").and_then(Object::as_name), Ok(b"Image")))
+}
+
+/// Soft masks and stencil masks must stay pixel exact, so they are left alone.
+fn collect_masks(document: &Document) -> HashSet<ObjectId> {
+    document
+        .objects
+        .values()
+        .filter(|object| is_image(object))
+        .filter_map(|object| object.as_stream().ok())
+        .flat_map(|stream| {
+            [b"SMask".as_slice(), b"Mask".as_slice()]
+                .into_iter()
+                .filter_map(|key| stream.dict.get(key).and_then(Object::as_reference).ok())
+        })
+        .collect()
+}
+
+fn color_components(document: &Document, color_space: &Object) -> Option<u32> {
+    let (_, color_space) = document.dereference(color_space).ok()?;
+    match color_space {
+        Object::Name(name) => match name.as_slice() {
+            b"DeviceGray" => Some(1),
+            b"DeviceRGB" => Some(3),
+            _ => None,
+        },
+        Object::Array(array) if matches!(array.first().map(Object::as_name), Some(Ok(b"ICCBased"))) => {
+            let (_, profile) = document.dereference(array.get(1)?).ok()?;
+            match profile.as_stream().ok()?.dict.get(b"N").and_then(Object::as_i64) {
+                Ok(1) => Some(1),
+                Ok(3) => Some(3),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Decodes the image into gray or RGB pixels, or `None` when its encoding is not supported.
+pub(crate) fn decode_image(document: &Document, stream: &Stream) -> Option<DynamicImage> {
+    let dict = &stream.dict;
+    if dict.get(b"ImageMask").and_then(Object::as_bool).unwrap_or(false) || dict.has(b"Decode") {
+        return None;
+    }
+    let width = u32::try_from(dict.get(b"Width").and_then(Object::as_i64).ok()?).ok()?;
+    let height = u32::try_from(dict.get(b"Height").and_then(Object::as_i64).ok()?).ok()?;
+    let components = color_components(document, dict.get(b"ColorSpace").ok()?)?;
+
+    let filters = stream.filters().unwrap_or_default();
+    let image = match filters.as_slice() {
+        [b"DCTDecode"] => {
+            let image = image::load_from_memory_with_format(&stream.content, ImageFormat::Jpeg).ok()?;
+            if components == 1 {
+                DynamicImage::ImageLuma8(image.to_luma8())
+            } else {
+                DynamicImage::ImageRgb8(image.to_rgb8())
+            }
+        }
+        [] | [b"FlateDecode"] | [b"LZWDecode"] => {
+            if dict.get(b"BitsPerComponent").and_then(Object::as_i64).ok()? != 8 {
+                return None;
+            }
+            let mut pixels = stream.get_plain_content().ok()?;
+            pixels.truncate((width * height * components) as usize);
+            if components == 1 {
+                DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, pixels)?)
+            } else {
+                DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, pixels)?)
+            }
+        }
+        _ => return None,
+    };
+    (image.width() == width && image.height() == height).then_some(image)
+}
+
+fn distinct_colors(image: &DynamicImage, limit: usize) -> HashSet<Vec<u8>> {
+    let mut colors = HashSet::new();
+    let channels = image.color().channel_count() as usize;
+    for pixel in image.as_bytes().chunks_exact(channels) {
+        if colors.insert(pixel.to_vec()) && colors.len() > limit {
+            break;
+        }
+    }
+    colors
+}
+
+/// Packs a black and white image into one bit per pixel, rows padded to whole bytes.
+fn pack_bilevel(image: &GrayImage) -> Vec<u8> {
+    let row_bytes = image.width().div_ceil(8) as usize;
+    let mut packed = vec![0u8; row_bytes * image.height() as usize];
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel.0[0] == 255 {
+            packed[y as usize * row_bytes + x as usize / 8] |= 0x80 >> (x % 8);
+        }
+    }
+    packed
+}
+
+struct EncodedImage {
+    width: u32,
+    height: u32,
+    bits_per_component: u32,
+    filter: &'static str,
+    content: Vec<u8>,
+}
+
+fn encode_image(image: &DynamicImage, quality: u8) -> Option<EncodedImage> {
+    let grayscale = image.color().channel_count() == 1;
+    let colors = distinct_colors(image, LINE_ART_MAX_COLORS);
+    let (bits_per_component, filter, content) = if colors.len() > LINE_ART_MAX_COLORS {
+        let mut content = Vec::new();
+        JpegEncoder::new_with_quality(&mut content, quality)
+            .encode_image(image)
+            .ok()?;
+        (8, "DCTDecode", content)
+    } else if grayscale && colors.iter().all(|color| color[0] == 0 || color[0] == 255) {
+        (1, "FlateDecode", deflate(&pack_bilevel(&image.to_luma8()), CompressionLevel::Max)?)
+    } else {
+        (8, "FlateDecode", deflate(image.as_bytes(), CompressionLevel::Max)?)
+    };
+    Some(EncodedImage {
+        width: image.width(),
+        height: image.height(),
+        bits_per_component,
+        filter,
+        content,
+    })
+}
+
+struct RewrittenImage {
+    encoded: EncodedImage,
+    downsampled: bool,
+    converted_to_gray: bool,
+}
+
+fn rewrite_image(
+    document: &Document,
+    stream: &Stream,
+    (shown_width, shown_height): (f32, f32),
+    options: &ImageOptions,
+) -> Option<RewrittenImage> {
+    let mut image = decode_image(document, stream)?;
+
+    let mut downsampled = false;
+    if let Some(max_dpi) = options.max_dpi {
+        if shown_width > 0.0 && shown_height > 0.0 {
+            let dpi = (image.width() as f32 * POINTS_PER_INCH / shown_width)
+                .min(image.height() as f32 * POINTS_PER_INCH / shown_height);
+            if dpi > max_dpi as f32 {
+                let scale = max_dpi as f32 / dpi;
+                let width = ((image.width() as f32 * scale).round() as u32).max(1);
+                let height = ((image.height() as f32 * scale).round() as u32).max(1);
+                image = image.resize_exact(width, height, FilterType::CatmullRom);
+                downsampled = true;
+            }
+        }
+    }
+
+    let converted = options.grayscale && image.color().channel_count() > 1;
+    if converted {
+        image = DynamicImage::ImageLuma8(image.to_luma8());
+    }
+
+    let encoded = encode_image(&image, options.jpeg_quality)?;
+    // Plain recompression only pays off when the image actually gets smaller.
+    (downsampled || converted || encoded.content.len() < stream.content.len()).then_some(RewrittenImage {
+        encoded,
+        downsampled,
+        converted_to_gray: converted,
+    })
+}
+
+/// Downsamples images shown above `options.max_dpi`, optionally converts them to
+/// grayscale and re-encodes them: JPEG for photographic content, Flate for line art.
+///
+/// The resolution of an image is taken from the largest size any page draws it at.
+/// Images that are never drawn, masks, color-keyed images (whose key must match exactly)
+/// and encodings that cannot be decoded (CMYK, indexed colors, JPEG 2000, ...) are left
+/// untouched.
+pub fn optimize_images(document: &mut Document, options: &ImageOptions) -> ImageSummary {
+    let usage = collect_image_usage(document);
+    let masks = collect_masks(document);
+    let image_ids: Vec<ObjectId> = document
+        .objects
+        .iter()
+        .filter(|(id, object)| is_image(object) && !masks.contains(id))
+        .map(|(id, _)| *id)
+        .collect();
+    let is_color_keyed = |stream: &Stream| matches!(stream.dict.get(b"Mask"), Ok(Object::Array(_)));
+
+    // Decoding and encoding dominate, and every image can be handled on its own.
+    let rewritten: Vec<(ObjectId, RewrittenImage)> = image_ids
+        .par_iter()
+        .filter_map(|id| {
+            let stream = document.objects.get(id)?.as_stream().ok()?;
+            if is_color_keyed(stream) {
+                return None;
+            }
+            let rewritten = rewrite_image(document, stream, *usage.get(id)?, options)?;
+            Some((*id, rewritten))
+        })
+        .collect();
+
+    let mut summary = ImageSummary {
+        images_found: image_ids.len(),
+        ..Default::default()
+    };
+    for (id, RewrittenImage { encoded, downsampled, converted_to_gray }) in rewritten {
+        let Some(Object::Stream(stream)) = document.objects.get_mut(&id) else {
+            continue;
+        };
+        summary.images_rewritten += 1;
+        summary.images_downsampled += usize::from(downsampled);
+        summary.bytes_saved += (stream.content.len() as u64).saturating_sub(encoded.content.len() as u64);
+
+        stream.dict.set("Width", encoded.width);
+        stream.dict.set("Height", encoded.height);
+        stream.dict.set("BitsPerComponent", encoded.bits_per_component);
+        if converted_to_gray {
+            stream.dict.set("ColorSpace", "DeviceGray");
+        }
+        stream.dict.set("Filter", encoded.filter);
+        stream.dict.remove(b"DecodeParms");
+        stream.set_content(encoded.content);
+    }
+    summary
+}
@@ -0,0 +1,424 @@
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use serde::Deserialize;
+
+use super::images::{multiply, Matrix};
+use super::loader::{get_inherited_attribute, get_page_box};
+use super::page_size::format_number;
+
+/// How source pages are placed on the output sheets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "LayoutValue")]
+pub enum Imposition {
+    /// `columns` x `rows` pages per sheet, left to right and top to bottom.
+    Grid { columns: u32, rows: u32 },
+    /// Two pages per sheet side, ordered so the printed, folded and stapled stack reads
+    /// in order (saddle stitch).
+    Booklet,
+}
+
+impl Imposition {
+    fn grid(&self) -> (u32, u32) {
+        match *self {
+            Imposition::Grid { columns, rows } => (columns, rows),
+            Imposition::Booklet => (2, 1),
+        }
+    }
+
+    /// Source page indices for each cell of each sheet, `None` for empty cells.
+    fn arrange(&self, page_count: usize) -> Vec<Vec<Option<usize>>> {
+        match *self {
+            Imposition::Grid { columns, rows } => {
+                let cells = (columns * rows) as usize;
+                (0..page_count.div_ceil(cells))
+                    .map(|sheet| {
+                        (0..cells)
+                            .map(|cell| Some(sheet * cells + cell).filter(|page| *page < page_count))
+                            .collect()
+                    })
+                    .collect()
+            }
+            Imposition::Booklet => {
+                let padded = page_count.div_ceil(4) * 4;
+                let page = |index: usize| Some(index).filter(|index| *index < page_count);
+                (0..padded / 4)
+                    .flat_map(|sheet| {
+                        let (front, back) = (2 * sheet, 2 * sheet + 1);
+                        [
+                            vec![page(padded - 1 - front), page(front)],
+                            vec![page(back), page(padded - 1 - back)],
+                        ]
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+impl FromStr for Imposition {
+    type Err = anyhow::Error;
+
+    /// Parses `2`, `4`, `2-up`, `COLUMNSxROWS` (e.g. `3x2`) or `booklet`.
+    fn from_str(layout: &str) -> Result<Self> {
+        let layout = layout.trim().to_lowercase();
+        let invalid = || {
+            format!(
+                "'{}' is not an n-up layout, expected 2, 4, COLUMNSxROWS or booklet",
+                layout
+            )
+        };
+        let count = |value: &str| -> Result<u32> {
+            let value = value.trim().parse::<u32>().with_context(invalid)?;
+            anyhow::ensure!(value > 0, "an n-up grid needs at least one row and column");
+            Ok(value)
+        };
+        match layout.trim_end_matches("-up") {
+            "booklet" => Ok(Imposition::Booklet),
+            "2" => Ok(Imposition::Grid { columns: 2, rows: 1 }),
+            "4" => Ok(Imposition::Grid { columns: 2, rows: 2 }),
+            grid => {
+                let (columns, rows) = grid.split_once('x').with_context(invalid)?;
+                Ok(Imposition::Grid {
+                    columns: count(columns)?,
+                    rows: count(rows)?,
+                })
+            }
+        }
+    }
+}
+
+/// Manifests may give the number of pages per sheet unquoted.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LayoutValue {
+    Text(String),
+    Count(u32),
+}
+
+impl TryFrom<LayoutValue> for Imposition {
+    type Error = String;
+
+    fn try_from(layout: LayoutValue) -> std::result::Result<Self, Self::Error> {
+        let layout = match layout {
+            LayoutValue::Text(layout) => layout,
+            LayoutValue::Count(count) => count.to_string(),
+        };
+        layout.parse().map_err(|error| format!("{:#}", error))
+    }
+}
+
+/// A source page turned into a form XObject, with the size it is displayed at.
+struct PlacedPage {
+    form_id: ObjectId,
+    /// Maps form space to an upright page of `width` x `height` at the origin.
+    upright: Matrix,
+    width: f32,
+    height: f32,
+    annotations: Vec<ObjectId>,
+}
+
+fn page_content(document: &Document, page_id: ObjectId) -> Result<Stream> {
+    let content_ids = document.get_page_contents(page_id);
+    // A single stream is copied as it is, whatever its filter, and not compressed again.
+    if let [content_id] = content_ids.as_slice() {
+        let stream = document.get_object(*content_id).and_then(Object::as_stream)?;
+        let mut dict = Dictionary::new();
+        for key in [b"Filter".as_slice(), b"DecodeParms"] {
+            if let Ok(value) = stream.dict.get(key) {
+                dict.set(key, value.clone());
+            }
+        }
+        return Ok(Stream::new(dict, stream.content.clone()).with_compression(false));
+    }
+    let mut content = Vec::new();
+    for content_id in content_ids {
+        let stream = document.get_object(content_id).and_then(Object::as_stream)?;
+        let decoded = match stream.filters() {
+            Ok(filters) if !filters.is_empty() => stream
+                .decompressed_content()
+                .context("content stream uses a filter that cannot be decoded")?,
+            _ => stream.content.clone(),
+        };
+        content.extend(decoded);
+        content.push(b'\n');
+    }
+    Ok(Stream::new(Dictionary::new(), content))
+}
+
+fn place_page(document: &mut Document, page_id: ObjectId) -> Result<PlacedPage> {
+    let [left, bottom, right, top] = get_page_box(document, page_id).context("page has no media box")?;
+    let (width, height) = (right - left, bottom.max(top) - bottom.min(top));
+    let rotation = get_inherited_attribute(document, page_id, b"Rotate")
+        .and_then(|rotate| rotate.as_i64().ok())
+        .unwrap_or(0)
+        .rem_euclid(360);
+    let resources = get_inherited_attribute(document, page_id, b"Resources")
+        .unwrap_or_else(|| Object::Dictionary(Dictionary::new()));
+    let annotations = match document.get_dictionary(page_id).and_then(|page| page.get(b"Annots")) {
+        Ok(Object::Array(annotations)) => annotations.clone(),
+        Ok(Object::Reference(id)) => document.get_object(*id).and_then(Object::as_array).cloned()?,
+        _ => Vec::new(),
+    };
+
+    let struct_parents = document
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"StructParents"))
+        .ok()
+        .cloned();
+
+    let mut form = page_content(document, page_id)?;
+    form.dict.set("Type", "XObject");
+    form.dict.set("Subtype", "Form");
+    form.dict.set(
+        "BBox",
+        vec![left.into(), bottom.into(), right.into(), top.into()],
+    );
+    form.dict.set("Resources", resources);
+    // The page's marked content moves into the form, and its parent tree entry with it.
+    if let Some(struct_parents) = struct_parents {
+        form.dict.set("StructParents", struct_parents);
+    }
+    let form_id = document.add_object(form);
+
+    // Move the box to the origin, then turn it the way viewers display the page.
+    let to_origin = [1.0, 0.0, 0.0, 1.0, -left, -bottom];
+    let (turn, width, height) = match rotation {
+        90 => ([0.0, -1.0, 1.0, 0.0, 0.0, width], height, width),
+        180 => ([-1.0, 0.0, 0.0, -1.0, width, height], width, height),
+        270 => ([0.0, 1.0, -1.0, 0.0, height, 0.0], height, width),
+        _ => ([1.0, 0.0, 0.0, 1.0, 0.0, 0.0], width, height),
+    };
+    Ok(PlacedPage {
+        form_id,
+        upright: multiply(&to_origin, &turn),
+        width,
+        height,
+        annotations: annotations.iter().filter_map(|id| id.as_reference().ok()).collect(),
+    })
+}
+
+fn transform_point(m: &Matrix, x: f32, y: f32) -> (f32, f32) {
+    (m[0] * x + m[2] * y + m[4], m[1] * x + m[3] * y + m[5])
+}
+
+fn move_annotation(document: &mut Document, annotation_id: ObjectId, sheet_id: ObjectId, m: &Matrix) {
+    let Ok(annotation) = document.get_dictionary_mut(annotation_id) else {
+        return;
+    };
+    let rect = annotation
+        .get(b"Rect")
+        .and_then(Object::as_array)
+        .ok()
+        .and_then(|rect| {
+            rect.iter()
+                .map(|value| value.as_float().ok())
+                .collect::<Option<Vec<f32>>>()
+        });
+    if let Some(&[x1, y1, x2, y2]) = rect.as_deref() {
+        let corners = [(x1, y1), (x2, y2)].map(|(x, y)| transform_point(m, x, y));
+        let (xs, ys) = (corners.map(|(x, _)| x), corners.map(|(_, y)| y));
+        annotation.set(
+            "Rect",
+            vec![
+                Object::Real(xs[0].min(xs[1])),
+                Object::Real(ys[0].min(ys[1])),
+                Object::Real(xs[0].max(xs[1])),
+                Object::Real(ys[0].max(ys[1])),
+            ],
+        );
+    }
+    annotation.set("P", sheet_id);
+}
+
+/// Points destinations (`[page /Fit]` and the like) at the sheet now showing the page.
+fn retarget_destinations(object: &mut Object, sheets: &HashMap<ObjectId, ObjectId>) {
+    match object {
+        Object::Array(items) => {
+            let is_destination = matches!(items.get(1), Some(Object::Name(_)));
+            match items.first_mut() {
+                Some(Object::Reference(page_id)) if is_destination => {
+                    if let Some(sheet_id) = sheets.get(page_id) {
+                        *page_id = *sheet_id;
+                    }
+                }
+                _ => items
+                    .iter_mut()
+                    .for_each(|item| retarget_destinations(item, sheets)),
+            }
+        }
+        Object::Dictionary(dict) => dict
+            .iter_mut()
+            .for_each(|(_, value)| retarget_destinations(value, sheets)),
+        _ => {}
+    }
+}
+
+/// Points structure elements and content item references at the sheet showing their
+/// page. Marked content now sits in the page's form XObject, so content item references
+/// name that stream, and marked-content ids given as plain integers become such references.
+fn retarget_structure(
+    object: &mut Object,
+    page: Option<ObjectId>,
+    sheets: &HashMap<ObjectId, ObjectId>,
+    forms: &HashMap<ObjectId, ObjectId>,
+) {
+    match object {
+        Object::Array(items) => items
+            .iter_mut()
+            .for_each(|item| retarget_structure(item, page, sheets, forms)),
+        Object::Dictionary(dict) => {
+            let page = match dict.get(b"Pg") {
+                Ok(Object::Reference(page_id)) => Some(*page_id),
+                Ok(_) => None,
+                Err(_) => page,
+            };
+            let placement = page.and_then(|page_id| Some((*sheets.get(&page_id)?, *forms.get(&page_id)?)));
+            if let Some((sheet_id, form_id)) = placement {
+                if dict.has(b"Pg") {
+                    dict.set("Pg", sheet_id);
+                }
+                if dict.has(b"MCID") && !dict.has(b"Stm") {
+                    dict.set("Stm", form_id);
+                }
+                let content_item = |mcid: i64| {
+                    Object::Dictionary(dictionary! { "Type" => "MCR", "MCID" => mcid, "Stm" => form_id })
+                };
+                match dict.get_mut(b"K") {
+                    Ok(Object::Integer(mcid)) => {
+                        let item = content_item(*mcid);
+                        dict.set("K", item);
+                    }
+                    Ok(Object::Array(kids)) => {
+                        for kid in kids.iter_mut() {
+                            if let Object::Integer(mcid) = kid {
+                                *kid = content_item(*mcid);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            dict.iter_mut()
+                .for_each(|(_, value)| retarget_structure(value, page, sheets, forms));
+        }
+        _ => {}
+    }
+}
+
+/// Replaces the pages of `document` with sheets showing several of them each, as
+/// form XObjects scaled into the cells of `imposition`. Sheets take the size of the
+/// first page, turned to whichever orientation leaves the pages larger.
+///
+/// Returns the 1-based number of the sheet each page now appears on, in page order.
+pub fn impose_pages(document: &mut Document, imposition: &Imposition) -> Result<Vec<usize>> {
+    let page_ids = document.get_pages().into_values().collect::<Vec<_>>();
+    let pages_id = document
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Pages"))
+        .and_then(Object::as_reference)
+        .context("Could not find the page tree")?;
+    let content_ids = page_ids
+        .iter()
+        .flat_map(|page_id| document.get_page_contents(*page_id))
+        .collect::<BTreeSet<_>>();
+    let placed = page_ids
+        .iter()
+        .enumerate()
+        .map(|(index, page_id)| {
+            place_page(document, *page_id).with_context(|| format!("Cannot impose page {}", index + 1))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let Some(first) = placed.first() else {
+        return Ok(Vec::new());
+    };
+
+    let (columns, rows) = imposition.grid();
+    let (columns, rows) = (columns as f32, rows as f32);
+    let cell_scale = |sheet_width: f32, sheet_height: f32| {
+        (sheet_width / columns / first.width).min(sheet_height / rows / first.height)
+    };
+    let (short, long) = (first.width.min(first.height), first.width.max(first.height));
+    let (sheet_width, sheet_height) = if cell_scale(long, short) >= cell_scale(short, long) {
+        (long, short)
+    } else {
+        (short, long)
+    };
+    let (cell_width, cell_height) = (sheet_width / columns, sheet_height / rows);
+
+    let mut sheets = HashMap::new();
+    let mut sheet_numbers = vec![0; placed.len()];
+    let mut forms = HashMap::new();
+    let mut sheet_ids = Vec::new();
+    for cells in imposition.arrange(placed.len()) {
+        let sheet_id = document.new_object_id();
+        let mut xobjects = Dictionary::new();
+        let mut content = String::new();
+        let mut annotations = Vec::new();
+        for (cell, page_index) in cells.into_iter().enumerate() {
+            let Some(page_index) = page_index else {
+                continue;
+            };
+            let page = &placed[page_index];
+            let (column, row) = ((cell as f32) % columns, (cell as f32 / columns).floor());
+            let scale = (cell_width / page.width).min(cell_height / page.height);
+            let cell_matrix = [
+                scale,
+                0.0,
+                0.0,
+                scale,
+                column * cell_width + (cell_width - page.width * scale) / 2.0,
+                sheet_height - (row + 1.0) * cell_height + (cell_height - page.height * scale) / 2.0,
+            ];
+            let m = multiply(&page.upright, &cell_matrix);
+            let name = format!("P{}", cell);
+            content.push_str(&format!(
+                "q {} cm /{} Do Q\n",
+                m.map(format_number).join(" "),
+                name
+            ));
+            xobjects.set(name, page.form_id);
+            for annotation_id in &page.annotations {
+                move_annotation(document, *annotation_id, sheet_id, &m);
+                annotations.push(Object::Reference(*annotation_id));
+            }
+            sheets.insert(page_ids[page_index], sheet_id);
+            sheet_numbers[page_index] = sheet_ids.len() + 1;
+            forms.insert(page_ids[page_index], page.form_id);
+        }
+
+        let content_id = document.add_object(Stream::new(Dictionary::new(), content.into_bytes()));
+        let mut sheet = dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), Object::Real(sheet_width), Object::Real(sheet_height)],
+            "Contents" => content_id,
+            "Resources" => dictionary! { "XObject" => xobjects },
+        };
+        if !annotations.is_empty() {
+            sheet.set("Annots", annotations);
+        }
+        document.objects.insert(sheet_id, Object::Dictionary(sheet));
+        sheet_ids.push(sheet_id);
+    }
+
+    for page_id in &page_ids {
+        document.objects.remove(page_id);
+    }
+    for content_id in content_ids {
+        document.objects.remove(&content_id);
+    }
+    for object in document.objects.values_mut() {
+        retarget_destinations(object, &sheets);
+        retarget_structure(object, None, &sheets, &forms);
+    }
+    let pages = document.get_dictionary_mut(pages_id)?;
+    pages.set("Count", sheet_ids.len() as u32);
+    pages.set(
+        "Kids",
+        sheet_ids.into_iter().map(Object::Reference).collect::<Vec<_>>(),
+    );
+    Ok(sheet_numbers)
+}
@@ -0,0 +1,494 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use lopdf::{dictionary, Document, Object, ObjectId, Stream};
+
+use super::compression::{deflate, CompressionLevel};
+use super::dedup::replace_references;
+use super::writer::{write_dictionary, write_indirect_object};
+
+/// Numbers in the linearization dictionary and the first-page trailer are padded to
+/// this width, so the start of the file keeps its length once the offsets are known.
+const NUMBER_WIDTH: usize = 10;
+
+fn padded(value: u64) -> String {
+    format!("{:<width$}", value, width = NUMBER_WIDTH)
+}
+
+fn bits_needed(value: u64) -> u64 {
+    (u64::BITS - value.leading_zeros()) as u64
+}
+
+/// Writes the bit-packed values of the hint tables, most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u8,
+    used: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u64) {
+        for bit in (0..bits).rev() {
+            self.current = (self.current << 1) | ((value >> bit) & 1) as u8;
+            self.used += 1;
+            if self.used == 8 {
+                self.bytes.push(self.current);
+                self.current = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    /// Every item of a hint table starts on a byte boundary.
+    fn align(&mut self) {
+        if self.used > 0 {
+            self.bytes.push(self.current << (8 - self.used));
+            self.current = 0;
+            self.used = 0;
+        }
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+fn is_structural(object: &Object) -> bool {
+    matches!(
+        object.type_name().unwrap_or(b""),
+        b"Catalog" | b"Pages" | b"Page"
+    )
+}
+
+fn is_skipped(object: &Object) -> bool {
+    matches!(
+        object.type_name().unwrap_or(b""),
+        b"ObjStm" | b"XRef" | b"Linearized"
+    )
+}
+
+fn collect_references(object: &Object, references: &mut Vec<ObjectId>) {
+    match object {
+        Object::Reference(id) => references.push(*id),
+        Object::Array(array) => array
+            .iter()
+            .for_each(|item| collect_references(item, references)),
+        Object::Dictionary(dictionary) => collect_dictionary_references(dictionary, references),
+        Object::Stream(stream) => collect_dictionary_references(&stream.dict, references),
+        _ => {}
+    }
+}
+
+fn collect_dictionary_references(dictionary: &lopdf::Dictionary, references: &mut Vec<ObjectId>) {
+    for (key, value) in dictionary {
+        // Parents lead back up the page or form field tree, away from the page.
+        if key != b"Parent" {
+            collect_references(value, references);
+        }
+    }
+}
+
+/// The page object followed by everything it needs, in depth-first order. Other pages
+/// and the page tree are never entered.
+fn page_objects(document: &Document, page_id: ObjectId) -> Vec<ObjectId> {
+    let mut objects = vec![page_id];
+    let mut seen = HashSet::from([page_id]);
+    let mut pending = Vec::new();
+    if let Some(page) = document.objects.get(&page_id) {
+        collect_references(page, &mut pending);
+    }
+    pending.reverse();
+
+    while let Some(id) = pending.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        let Some(object) = document.objects.get(&id) else {
+            continue;
+        };
+        if is_structural(object) || is_skipped(object) {
+            continue;
+        }
+        objects.push(id);
+        let mut references = Vec::new();
+        collect_references(object, &mut references);
+        pending.extend(references.into_iter().rev());
+    }
+    objects
+}
+
+/// Objects grouped into the parts of a linearized file (ISO 32000-1, Annex F).
+struct Parts {
+    catalog: ObjectId,
+    first_page: Vec<ObjectId>,
+    other_pages: Vec<Vec<ObjectId>>,
+    /// For every page but the first, its entries in the shared object hint table.
+    shared_references: Vec<Vec<u64>>,
+    shared: Vec<ObjectId>,
+    rest: Vec<ObjectId>,
+}
+
+fn split_into_parts(document: &Document) -> Result<Parts> {
+    let catalog = document
+        .trailer
+        .get(b"Root")
+        .and_then(Object::as_reference)
+        .context("The document has no catalog")?;
+    let pages_objects: Vec<Vec<ObjectId>> = document
+        .page_iter()
+        .map(|page_id| page_objects(document, page_id))
+        .collect();
+    anyhow::ensure!(!pages_objects.is_empty(), "The document has no pages");
+
+    let mut owners: HashMap<ObjectId, usize> = HashMap::new();
+    for objects in &pages_objects {
+        for id in objects {
+            *owners.entry(*id).or_default() += 1;
+        }
+    }
+
+    let first_page = pages_objects[0].clone();
+    let first_page_index: HashMap<ObjectId, usize> = first_page
+        .iter()
+        .enumerate()
+        .map(|(index, id)| (*id, index))
+        .collect();
+
+    let mut shared = Vec::new();
+    let mut shared_index: HashMap<ObjectId, usize> = HashMap::new();
+    let mut other_pages = Vec::new();
+    let mut shared_references = Vec::new();
+    for objects in &pages_objects[1..] {
+        let mut private = Vec::new();
+        let mut references = Vec::new();
+        for id in objects {
+            if owners[id] == 1 {
+                private.push(*id);
+            } else if let Some(index) = first_page_index.get(id) {
+                references.push(*index as u64);
+            } else {
+                let index = *shared_index.entry(*id).or_insert_with(|| {
+                    shared.push(*id);
+                    shared.len() - 1
+                });
+                references.push((first_page.len() + index) as u64);
+            }
+        }
+        references.sort_unstable();
+        references.dedup();
+        other_pages.push(private);
+        shared_references.push(references);
+    }
+
+    let placed: HashSet<ObjectId> = pages_objects.iter().flatten().copied().collect();
+    let rest = document
+        .objects
+        .iter()
+        .filter(|(id, object)| **id != catalog && !placed.contains(id) && !is_skipped(object))
+        .map(|(id, _)| *id)
+        .collect();
+
+    Ok(Parts {
+        catalog,
+        first_page,
+        other_pages,
+        shared_references,
+        shared,
+        rest,
+    })
+}
+
+fn serialize(document: &Document, id: ObjectId, new_id: u32, renumbering: &BTreeMap<ObjectId, ObjectId>) -> Vec<u8> {
+    let mut object = document.objects[&id].clone();
+    replace_references(&mut object, renumbering);
+    if let Object::Stream(stream) = &mut object {
+        stream.dict.set("Length", stream.content.len() as i64);
+    }
+    let mut bytes = Vec::new();
+    let _ = write_indirect_object(&mut bytes, (new_id, 0), &object);
+    bytes
+}
+
+struct PageHint {
+    objects: u64,
+    length: u64,
+    shared_references: Vec<u64>,
+}
+
+fn page_offset_hint_table(pages: &[PageHint], first_page_offset: u64) -> Vec<u8> {
+    let least_objects = pages.iter().map(|page| page.objects).min().unwrap_or(0);
+    let most_objects = pages.iter().map(|page| page.objects).max().unwrap_or(0);
+    let least_length = pages.iter().map(|page| page.length).min().unwrap_or(0);
+    let most_length = pages.iter().map(|page| page.length).max().unwrap_or(0);
+    let most_references = pages
+        .iter()
+        .map(|page| page.shared_references.len() as u64)
+        .max()
+        .unwrap_or(0);
+    let greatest_reference = pages
+        .iter()
+        .flat_map(|page| page.shared_references.iter().copied())
+        .max()
+        .unwrap_or(0);
+
+    let objects_bits = bits_needed(most_objects - least_objects);
+    let length_bits = bits_needed(most_length - least_length);
+    let references_bits = bits_needed(most_references);
+    let reference_bits = bits_needed(greatest_reference);
+
+    let mut table = BitWriter::default();
+    table.write(least_objects, 32);
+    table.write(first_page_offset, 32);
+    table.write(objects_bits, 16);
+    table.write(least_length, 32);
+    table.write(length_bits, 16);
+    // Content stream offsets and lengths are left out, like most writers do, since
+    // viewers do not rely on them.
+    table.write(0, 32);
+    table.write(0, 16);
+    table.write(0, 32);
+    table.write(0, 16);
+    table.write(references_bits, 16);
+    table.write(reference_bits, 16);
+    table.write(0, 16);
+    table.write(1, 16);
+
+    for page in pages {
+        table.write(page.objects - least_objects, objects_bits);
+    }
+    table.align();
+    for page in pages {
+        table.write(page.length - least_length, length_bits);
+    }
+    table.align();
+    for page in pages {
+        table.write(page.shared_references.len() as u64, references_bits);
+    }
+    table.align();
+    for page in pages {
+        for reference in &page.shared_references {
+            table.write(*reference, reference_bits);
+        }
+    }
+    table.into_bytes()
+}
+
+fn shared_object_hint_table(
+    group_lengths: &[u64],
+    first_page_groups: usize,
+    first_shared: Option<(u32, u64)>,
+) -> Vec<u8> {
+    let least_length = group_lengths.iter().copied().min().unwrap_or(0);
+    let most_length = group_lengths.iter().copied().max().unwrap_or(0);
+    let length_bits = bits_needed(most_length - least_length);
+    let (first_shared_id, first_shared_offset) = first_shared.unwrap_or((0, 0));
+
+    let mut table = BitWriter::default();
+    table.write(first_shared_id as u64, 32);
+    table.write(first_shared_offset, 32);
+    table.write(first_page_groups as u64, 32);
+    table.write(group_lengths.len() as u64, 32);
+    // Every group holds a single object.
+    table.write(0, 16);
+    table.write(least_length, 32);
+    table.write(length_bits, 16);
+
+    for length in group_lengths {
+        table.write(length - least_length, length_bits);
+    }
+    table.align();
+    for _ in group_lengths {
+        table.write(0, 1);
+    }
+    table.into_bytes()
+}
+
+/// Writes `document` as a linearized ("fast web view") file: the catalog, the first
+/// page and a hint stream come first, so viewers can show page one while the rest of
+/// the file is still downloading.
+pub fn write_linearized<W: Write>(document: &Document, target: &mut W) -> Result<()> {
+    let parts = split_into_parts(document)?;
+
+    // The main cross-reference table covers the objects after the first page, the
+    // first-page table the linearization dictionary, catalog, first page and hints.
+    let main_ids: Vec<ObjectId> = parts
+        .other_pages
+        .iter()
+        .flatten()
+        .chain(&parts.shared)
+        .chain(&parts.rest)
+        .copied()
+        .collect();
+    let first_section_start = main_ids.len() as u32 + 1;
+    let linearization_id = first_section_start;
+    let catalog_id = first_section_start + 1;
+    let first_page_ids = catalog_id + 1..catalog_id + 1 + parts.first_page.len() as u32;
+    let hint_id = first_page_ids.end;
+    let size = hint_id + 1;
+
+    let mut renumbering = BTreeMap::new();
+    renumbering.insert(parts.catalog, (catalog_id, 0));
+    for (id, new_id) in parts.first_page.iter().zip(first_page_ids.clone()) {
+        renumbering.insert(*id, (new_id, 0));
+    }
+    for (id, new_id) in main_ids.iter().zip(1..) {
+        renumbering.insert(*id, (new_id, 0));
+    }
+
+    let catalog = serialize(document, parts.catalog, catalog_id, &renumbering);
+    let first_page: Vec<Vec<u8>> = parts
+        .first_page
+        .iter()
+        .zip(first_page_ids.clone())
+        .map(|(id, new_id)| serialize(document, *id, new_id, &renumbering))
+        .collect();
+    let main: Vec<Vec<u8>> = main_ids
+        .iter()
+        .zip(1..)
+        .map(|(id, new_id)| serialize(document, *id, new_id, &renumbering))
+        .collect();
+
+    let mut trailer = dictionary! {
+        "Size" => size,
+        "Root" => (catalog_id, 0),
+    };
+    if let Ok(info) = document.trailer.get(b"Info") {
+        let mut info = info.clone();
+        replace_references(&mut info, &renumbering);
+        trailer.set("Info", info);
+    }
+    if let Ok(id) = document.trailer.get(b"ID") {
+        trailer.set("ID", id.clone());
+    }
+    let mut trailer_bytes = Vec::new();
+    write_dictionary(&mut trailer_bytes, &trailer)?;
+    trailer_bytes.truncate(trailer_bytes.len() - 2);
+
+    let mut header = format!("%PDF-{}\n%", document.version).into_bytes();
+    header.extend_from_slice(&document.binary_mark);
+    header.push(b'\n');
+
+    let page_count = document.get_pages().len() as u64;
+    let linearization = |file_length: u64, hint: (u64, u64), first_page_end: u64, main_xref_entry: u64| {
+        format!(
+            "{} 0 obj\n<</Linearized 1/L {}/H [{} {}]/O {}/E {}/N {}/T {}>>\nendobj\n",
+            linearization_id,
+            padded(file_length),
+            padded(hint.0),
+            padded(hint.1),
+            first_page_ids.start,
+            padded(first_page_end),
+            page_count,
+            padded(main_xref_entry),
+        )
+    };
+    let first_xref = |offsets: &[u64], main_xref: u64| {
+        let mut xref = format!("xref\n{} {}\n", first_section_start, size - first_section_start).into_bytes();
+        for offset in offsets {
+            xref.extend(format!("{:010} 00000 n \n", offset).into_bytes());
+        }
+        xref.extend_from_slice(b"trailer\n");
+        xref.extend_from_slice(&trailer_bytes);
+        xref.extend(format!("/Prev {}>>\nstartxref\n0\n%%EOF\n", padded(main_xref)).into_bytes());
+        xref
+    };
+    let first_section_objects = (size - first_section_start) as usize;
+    let first_xref_offset = (header.len() + linearization(0, (0, 0), 0, 0).len()) as u64;
+    let prefix_length = first_xref_offset + first_xref(&vec![0; first_section_objects], 0).len() as u64;
+
+    // Hint tables give offsets as if the hint stream was not in the file.
+    let catalog_offset = prefix_length;
+    let hint_offset = catalog_offset + catalog.len() as u64;
+    let mut position = hint_offset;
+    let mut first_page_offsets = Vec::new();
+    for object in &first_page {
+        first_page_offsets.push(position);
+        position += object.len() as u64;
+    }
+    let first_page_end = position;
+    let mut main_offsets = Vec::new();
+    for object in &main {
+        main_offsets.push(position);
+        position += object.len() as u64;
+    }
+    let main_xref_offset = position;
+
+    let mut pages = vec![PageHint {
+        objects: first_page.len() as u64,
+        length: first_page_end - first_page_offsets[0],
+        shared_references: Vec::new(),
+    }];
+    let mut next_object = 0;
+    for (objects, shared_references) in parts.other_pages.iter().zip(&parts.shared_references) {
+        let start = main_offsets[next_object];
+        next_object += objects.len();
+        let end = main_offsets.get(next_object).copied().unwrap_or(main_xref_offset);
+        pages.push(PageHint {
+            objects: objects.len() as u64,
+            length: end - start,
+            shared_references: shared_references.clone(),
+        });
+    }
+    let first_shared = (!parts.shared.is_empty()).then(|| (next_object as u32 + 1, main_offsets[next_object]));
+    let group_lengths: Vec<u64> = first_page
+        .iter()
+        .chain(&main[next_object..next_object + parts.shared.len()])
+        .map(|object| object.len() as u64)
+        .collect();
+
+    let mut hint_data = page_offset_hint_table(&pages, first_page_offsets[0]);
+    let shared_table_offset = hint_data.len();
+    hint_data.extend(shared_object_hint_table(&group_lengths, first_page.len(), first_shared));
+    let hint_stream = Stream::new(
+        dictionary! {
+            "S" => shared_table_offset as i64,
+            "Filter" => "FlateDecode",
+        },
+        deflate(&hint_data, CompressionLevel::Max).context("Failed to compress hint stream")?,
+    );
+    let mut hint = Vec::new();
+    write_indirect_object(&mut hint, (hint_id, 0), &Object::Stream(hint_stream))?;
+    let hint_length = hint.len() as u64;
+
+    let main_xref_header = format!("xref\n0 {}", first_section_start);
+    let main_xref_offset = main_xref_offset + hint_length;
+    let mut main_xref = format!("{}\n0000000000 65535 f \n", main_xref_header).into_bytes();
+    for offset in &main_offsets {
+        writeln!(main_xref, "{:010} 00000 n ", offset + hint_length)?;
+    }
+    write!(
+        main_xref,
+        "trailer\n<</Size {}>>\nstartxref\n{}\n%%EOF\n",
+        first_section_start, first_xref_offset
+    )?;
+
+    let mut first_section_offsets = vec![header.len() as u64, catalog_offset];
+    first_section_offsets.extend(first_page_offsets.iter().map(|offset| offset + hint_length));
+    first_section_offsets.push(hint_offset);
+    let file_length = main_xref_offset + main_xref.len() as u64;
+
+    let mut prefix = header;
+    prefix.extend(
+        linearization(
+            file_length,
+            (hint_offset, hint_length),
+            first_page_end + hint_length,
+            main_xref_offset + main_xref_header.len() as u64,
+        )
+        .into_bytes(),
+    );
+    prefix.extend(first_xref(&first_section_offsets, main_xref_offset));
+    debug_assert_eq!(prefix.len() as u64, prefix_length);
+
+    target.write_all(&prefix)?;
+    target.write_all(&catalog)?;
+    target.write_all(&hint)?;
+    for object in first_page.iter().chain(&main) {
+        target.write_all(object)?;
+    }
+    target.write_all(&main_xref)?;
+    Ok(())
+}
@@ -1,15 +1,65 @@
 use std::collections::BTreeMap;
+use std::path::Path;
+use anyhow::{ Context, Result };
 use lopdf::{ Bookmark, Document, Object, ObjectId };
 
+use super::attachments::{ embedded_files, OriginalFile };
+use super::blank::{ is_blank_page, BlankPageOptions };
+use super::crop::{ self, PageCrop };
+use super::metadata::DocumentMetadata;
+use super::options::{ PageRanges, PageRotation, PageRotations };
+use super::version::{ PdfFeature, PdfVersion };
+
 pub trait DocumentLoader {
-    fn load(&self) -> MergableDocument;
+    fn load(&self) -> Result<MergableDocument>;
 }
 
 pub struct MergableDocument {
     original_filename: String,
+    bookmark_title: Option<String>,
+    /// Requested rotation of each page in page order, empty when nothing is rotated.
+    page_rotations: Vec<Option<PageRotation>>,
+    /// Page number in the input file of every page left, in page order.
+    page_numbers: Vec<u32>,
+    /// Page numbers in the input file of the blank pages that were removed.
+    removed_blank_pages: Vec<u32>,
+    /// The input file as read, embedded in the output when set.
+    original_file: Option<OriginalFile>,
     pdf: Document,
 }
 
+pub(crate) fn get_inherited_attribute(pdf: &Document, page_id: ObjectId, key: &[u8]) -> Option<Object> {
+    let mut node = pdf.get_dictionary(page_id).ok()?;
+    loop {
+        if let Ok(value) = node.get(key) {
+            return Some(value.clone());
+        }
+        let parent_id = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = pdf.get_dictionary(parent_id).ok()?;
+    }
+}
+
+/// The visible area of a page as `[left, bottom, right, top]`: its crop box, falling back
+/// to the media box.
+pub(crate) fn get_page_box(pdf: &Document, page_id: ObjectId) -> Option<[f32; 4]> {
+    let page_box = get_inherited_attribute(pdf, page_id, b"CropBox")
+        .or_else(|| get_inherited_attribute(pdf, page_id, b"MediaBox"))?;
+    let page_box = match page_box {
+        Object::Reference(id) => pdf.get_object(id).ok()?.clone(),
+        page_box => page_box,
+    };
+    let values = page_box
+        .as_array()
+        .ok()?
+        .iter()
+        .map(|value| value.as_float().ok())
+        .collect::<Option<Vec<f32>>>()?;
+    let &[x1, y1, x2, y2] = values.as_slice() else {
+        return None;
+    };
+    Some([x1.min(x2), y1.min(y2), x1.max(x2), y1.max(y2)])
+}
+
 impl MergableDocument {
     pub fn get_pages(&self) -> BTreeMap<(u32, u16), Object> {
         self.pdf
@@ -23,6 +73,19 @@ impl MergableDocument {
         self.pdf.objects.clone()
     }
 
+    pub fn get_page_count(&self) -> usize {
+        self.pdf.get_pages().len()
+    }
+
+    /// Whether no page is left, which leaves the document out of the merge.
+    pub fn is_empty(&self) -> bool {
+        self.get_page_count() == 0
+    }
+
+    pub fn get_object_count(&self) -> usize {
+        self.pdf.objects.len()
+    }
+
     pub fn renumber(&mut self, offset: u32) -> &mut MergableDocument {
         self.pdf.renumber_objects_with(offset);
         self
@@ -33,7 +96,136 @@ impl MergableDocument {
     }
 
     pub fn get_filename_based_bookmark(&self, page_id: ObjectId) -> Bookmark {
-        Bookmark::new(self.original_filename.clone(), [0.0, 0.0, 1.0], 0, page_id)
+        let title = self.bookmark_title.as_ref().unwrap_or(&self.original_filename);
+        Bookmark::new(title.clone(), [0.0, 0.0, 1.0], 0, page_id)
+    }
+
+    pub fn set_bookmark_title(&mut self, title: &str) -> &mut MergableDocument {
+        self.bookmark_title = Some(title.to_string());
+        self
+    }
+
+    pub fn select_pages(&mut self, selection: &PageRanges) -> Result<&mut MergableDocument> {
+        let pages = self.pdf.get_pages();
+        selection.validate(pages.len() as u32)?;
+
+        self.retain_pages(|page_number| selection.contains(page_number));
+        Ok(self)
+    }
+
+    /// Deletes the pages whose current page number `keep` rejects.
+    fn retain_pages(&mut self, keep: impl Fn(u32) -> bool) {
+        let removed_pages = self.pdf
+            .get_pages()
+            .into_keys()
+            .filter(|page_number| !keep(*page_number))
+            .collect::<Vec<u32>>();
+        self.pdf.delete_pages(&removed_pages);
+        let mut page_number = 0;
+        self.page_numbers.retain(|_| {
+            page_number += 1;
+            keep(page_number)
+        });
+        if !self.page_rotations.is_empty() {
+            let mut page_number = 0;
+            self.page_rotations.retain(|_| {
+                page_number += 1;
+                keep(page_number)
+            });
+        }
+    }
+
+    /// Removes pages that show nothing. An input whose pages are all blank is left empty,
+    /// see [`Self::is_empty`].
+    pub fn remove_blank_pages(&mut self, options: &BlankPageOptions) -> &mut MergableDocument {
+        let blank_pages = self.pdf
+            .get_pages()
+            .into_iter()
+            .filter(|(_, page_id)| is_blank_page(&self.pdf, *page_id, options))
+            .map(|(page_number, _)| page_number)
+            .collect::<Vec<u32>>();
+        self.removed_blank_pages = blank_pages
+            .iter()
+            .map(|page_number| self.page_numbers[(*page_number as usize) - 1])
+            .collect();
+        self.retain_pages(|page_number| !blank_pages.contains(&page_number));
+        self
+    }
+
+    /// Page numbers in the input file of the blank pages [`Self::remove_blank_pages`] removed.
+    pub fn get_removed_blank_pages(&self) -> &[u32] {
+        &self.removed_blank_pages
+    }
+
+    /// Sets the crop box of every page, before any selection.
+    pub fn crop_pages(&mut self, crop: &PageCrop) -> Result<&mut MergableDocument> {
+        crop::crop_pages(&mut self.pdf, crop)?;
+        Ok(self)
+    }
+
+    /// Records how pages are rotated, by their page number before any selection. The
+    /// `Rotate` entries are updated when the pages are merged.
+    pub fn rotate_pages(&mut self, rotations: &PageRotations) -> Result<&mut MergableDocument> {
+        let page_count = self.pdf.get_pages().len() as u32;
+        rotations.validate(page_count)?;
+        self.page_rotations = (1..=page_count).map(|page| rotations.for_page(page)).collect();
+        Ok(self)
+    }
+
+    fn is_landscape(&self, page_id: ObjectId, rotation: i64) -> bool {
+        let Some([left, bottom, right, top]) = get_page_box(&self.pdf, page_id) else {
+            return false;
+        };
+        let (width, height) = (right - left, top - bottom);
+        if rotation % 180 == 0 { width > height } else { height > width }
+    }
+
+    /// The `Rotate` entry of every page that is rotated, including what the page already had.
+    pub fn get_page_rotations(&self) -> BTreeMap<ObjectId, i64> {
+        self.pdf
+            .page_iter()
+            .zip(&self.page_rotations)
+            .filter_map(|(page_id, rotation)| {
+                let current = get_inherited_attribute(&self.pdf, page_id, b"Rotate")
+                    .and_then(|rotate| rotate.as_i64().ok())
+                    .unwrap_or(0)
+                    .rem_euclid(360);
+                let degrees = match (*rotation)? {
+                    PageRotation::Clockwise(rotation) => rotation.degrees(),
+                    PageRotation::Auto if self.is_landscape(page_id, current) => 90,
+                    PageRotation::Auto => 0,
+                };
+                Some((page_id, (current + degrees).rem_euclid(360)))
+            })
+            .collect()
+    }
+
+    /// The keys and file specifications of the files embedded in the document.
+    pub fn get_embedded_files(&self) -> Vec<(Vec<u8>, Object)> {
+        embedded_files(&self.pdf)
+    }
+
+    /// Embeds the file at `path` in the merged output, with its name, modification date
+    /// and checksum.
+    pub fn attach_original_file(&mut self, path: &Path) -> Result<&mut MergableDocument> {
+        self.original_file = Some(OriginalFile::read(path)?);
+        Ok(self)
+    }
+
+    pub fn take_original_file(&mut self) -> Option<OriginalFile> {
+        self.original_file.take()
+    }
+
+    pub fn get_metadata(&self) -> DocumentMetadata {
+        DocumentMetadata::from_document(&self.pdf)
+    }
+
+    pub fn get_version(&self) -> PdfVersion {
+        PdfVersion::of_document(&self.pdf)
+    }
+
+    pub fn get_features(&self) -> Vec<PdfFeature> {
+        PdfFeature::of_document(&self.pdf)
     }
 
     pub fn get_max_id(&self) -> u32 {
@@ -43,38 +235,105 @@ impl MergableDocument {
     pub fn from_document(original_filename: &str, pdf: Document) -> MergableDocument {
         MergableDocument {
             original_filename: original_filename.to_string(),
+            bookmark_title: None,
+            page_rotations: Vec::new(),
+            page_numbers: (1..=pdf.get_pages().len() as u32).collect(),
+            removed_blank_pages: Vec::new(),
+            original_file: None,
             pdf,
         }
     }
 }
 
 pub mod fs {
+    use std::path::Path;
+
     use lopdf::Document;
 
     use super::*;
+    use crate::inputs::{detect_input_kind, InputKind};
+    use crate::merge::image_pages::{load_image_document, ImagePageOptions};
+    use crate::merge::text_pages::{TextMergingSource, TextPageOptions};
 
-    #[derive(Debug, Clone)]
-    pub struct FileSystemMergingDestination<'a> {
-        pub output_file: &'a str,
+    #[derive(Debug, Clone, Default)]
+    pub struct FileSystemMergingDestination {
+        pub output_file: String,
     }
 
-    #[derive(Debug, Clone)]
-    pub struct FileSystemMergingSource<'a> {
-        pub input_file: &'a str,
+    #[derive(Debug, Clone, Default)]
+    pub struct FileSystemMergingSource {
+        pub input_file: String,
+        pub pages: Option<PageRanges>,
+        pub rotation: Option<PageRotations>,
+        pub crop: Option<PageCrop>,
+        /// How the pages of image inputs are sized.
+        pub image: ImagePageOptions,
+        /// How plain-text inputs are laid out.
+        pub text: TextPageOptions,
+        pub bookmark: Option<String>,
+        pub password: Option<String>,
+    }
+
+    impl FileSystemMergingSource {
+        pub fn new(input_file: &str) -> Self {
+            FileSystemMergingSource {
+                input_file: input_file.to_string(),
+                ..Default::default()
+            }
+        }
     }
 
-    impl DocumentLoader for FileSystemMergingSource<'_> {
-        fn load(&self) -> MergableDocument {
-            let pdf = Document::load(self.input_file).unwrap_or_else(|_|
-                panic!("Failed to load {}", self.input_file)
-            );
+    impl DocumentLoader for FileSystemMergingSource {
+        fn load(&self) -> Result<MergableDocument> {
+            let kind = detect_input_kind(Path::new(&self.input_file))
+                .with_context(|| format!("Failed to load {}", self.input_file))?;
             let original_filename = self.input_file
                 .split(std::path::MAIN_SEPARATOR)
-                .last()
-                .unwrap()
-                .to_string();
+                .next_back()
+                .unwrap();
+            let mut document = match kind {
+                Some(InputKind::Pdf) => {
+                    let pdf = (match &self.password {
+                        Some(password) => Document::load_with_password(&self.input_file, password),
+                        None => Document::load(&self.input_file),
+                    }).with_context(|| format!("Failed to load {}", self.input_file))?;
+                    MergableDocument::from_document(original_filename, pdf)
+                }
+                Some(InputKind::Image(format)) => {
+                    let pdf = load_image_document(Path::new(&self.input_file), format, &self.image)
+                        .with_context(|| format!("Failed to load {}", self.input_file))?;
+                    MergableDocument::from_document(original_filename, pdf)
+                }
+                Some(InputKind::Text) => TextMergingSource {
+                    input_file: self.input_file.clone(),
+                    options: self.text,
+                }.load()?,
+                None => anyhow::bail!(
+                    "Failed to load {}: not a PDF file, a JPEG, PNG or TIFF image or a .txt file",
+                    self.input_file
+                ),
+            };
+            if let Some(title) = &self.bookmark {
+                document.set_bookmark_title(title);
+            }
+            if let Some(crop) = &self.crop {
+                document
+                    .crop_pages(crop)
+                    .with_context(|| format!("Invalid crop for {}", self.input_file))?;
+            }
+            // Rotations refer to page numbers before the selection.
+            if let Some(rotation) = &self.rotation {
+                document
+                    .rotate_pages(rotation)
+                    .with_context(|| format!("Invalid rotation for {}", self.input_file))?;
+            }
+            if let Some(pages) = &self.pages {
+                document
+                    .select_pages(pages)
+                    .with_context(|| format!("Invalid page selection for {}", self.input_file))?;
+            }
 
-            MergableDocument { pdf, original_filename }
+            Ok(document)
         }
     }
 }
@@ -0,0 +1,250 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lopdf::{decode_text_string, dictionary, text_string, Dictionary, Document, Object, Stream};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DocumentMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    pub creator: Option<String>,
+    pub producer: Option<String>,
+    /// 1-based index of the input whose Info dictionary fills in the fields left empty.
+    pub inherit_from: Option<usize>,
+}
+
+impl DocumentMetadata {
+    fn entries(&self) -> [(&'static str, &Option<String>); 6] {
+        [
+            ("Title", &self.title),
+            ("Author", &self.author),
+            ("Subject", &self.subject),
+            ("Keywords", &self.keywords),
+            ("Creator", &self.creator),
+            ("Producer", &self.producer),
+        ]
+    }
+
+    fn entries_mut(&mut self) -> [(&'static str, &mut Option<String>); 6] {
+        [
+            ("Title", &mut self.title),
+            ("Author", &mut self.author),
+            ("Subject", &mut self.subject),
+            ("Keywords", &mut self.keywords),
+            ("Creator", &mut self.creator),
+            ("Producer", &mut self.producer),
+        ]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries().iter().all(|(_, value)| value.is_none())
+    }
+
+    /// Reads the Info dictionary of `document`. Entries that are missing or not text
+    /// are left empty.
+    pub fn from_document(document: &Document) -> Self {
+        let mut metadata = DocumentMetadata::default();
+        let info = document
+            .trailer
+            .get(b"Info")
+            .and_then(|info| document.dereference(info))
+            .and_then(|(_, info)| info.as_dict());
+        if let Ok(info) = info {
+            for (key, value) in metadata.entries_mut() {
+                *value = info
+                    .get(key.as_bytes())
+                    .and_then(decode_text_string)
+                    .ok()
+                    .filter(|text| !text.trim().is_empty());
+            }
+        }
+        metadata
+    }
+
+    /// Fills the fields that are not set yet from `other`.
+    pub fn inherit(&mut self, other: DocumentMetadata) {
+        for ((_, value), (_, inherited)) in self.entries_mut().into_iter().zip(other.entries()) {
+            if value.is_none() {
+                value.clone_from(inherited);
+            }
+        }
+    }
+
+    /// Replaces the fields `other` sets, keeping the rest.
+    pub fn override_with(&mut self, other: DocumentMetadata) {
+        for ((_, value), (_, overriding)) in self.entries_mut().into_iter().zip(other.entries()) {
+            if overriding.is_some() {
+                value.clone_from(overriding);
+            }
+        }
+        if other.inherit_from.is_some() {
+            self.inherit_from = other.inherit_from;
+        }
+    }
+}
+
+/// A point in time in UTC, formatted for Info dictionaries and XMP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Timestamp::from_system_time(SystemTime::now())
+    }
+
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as i64);
+        let (days, seconds_of_day) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+
+        // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+        let shifted = days + 719_468;
+        let era = shifted.div_euclid(146_097);
+        let day_of_era = shifted.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+        let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        Timestamp {
+            year,
+            month,
+            day,
+            hour: (seconds_of_day / 3600) as u32,
+            minute: (seconds_of_day % 3600 / 60) as u32,
+            second: (seconds_of_day % 60) as u32,
+        }
+    }
+
+    /// `D:YYYYMMDDHHmmSSZ`, the date format of Info dictionaries.
+    pub fn to_pdf_date(self) -> String {
+        format!(
+            "D:{:04}{:02}{:02}{:02}{:02}{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+
+    /// ISO 8601, the date format of XMP.
+    pub fn to_xmp_date(self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Builds an XMP packet carrying the same values as the Info dictionary, identifying the
+/// file as PDF/A-2b when `pdfa` is set.
+pub fn xmp_packet(metadata: &DocumentMetadata, created: Timestamp, pdfa: bool) -> String {
+    let language_alternative = |text: &str| {
+        format!(
+            "<rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt>",
+            escape_xml(text)
+        )
+    };
+    let mut properties = vec!["<dc:format>application/pdf</dc:format>".to_string()];
+    if let Some(title) = &metadata.title {
+        properties.push(format!("<dc:title>{}</dc:title>", language_alternative(title)));
+    }
+    if let Some(author) = &metadata.author {
+        properties.push(format!(
+            "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>",
+            escape_xml(author)
+        ));
+    }
+    if let Some(subject) = &metadata.subject {
+        properties.push(format!("<dc:description>{}</dc:description>", language_alternative(subject)));
+    }
+    if let Some(keywords) = &metadata.keywords {
+        properties.push(format!("<pdf:Keywords>{}</pdf:Keywords>", escape_xml(keywords)));
+    }
+    if let Some(producer) = &metadata.producer {
+        properties.push(format!("<pdf:Producer>{}</pdf:Producer>", escape_xml(producer)));
+    }
+    if let Some(creator) = &metadata.creator {
+        properties.push(format!("<xmp:CreatorTool>{}</xmp:CreatorTool>", escape_xml(creator)));
+    }
+    let date = created.to_xmp_date();
+    for property in ["CreateDate", "ModifyDate", "MetadataDate"] {
+        properties.push(format!("<xmp:{property}>{date}</xmp:{property}>"));
+    }
+
+    let mut packet = String::from("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+    packet.push_str("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n");
+    packet.push_str("<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n");
+    packet.push_str(
+        "<rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+         xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\" xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">\n",
+    );
+    for property in properties {
+        packet.push_str(&property);
+        packet.push('\n');
+    }
+    packet.push_str("</rdf:Description>\n");
+    if pdfa {
+        packet.push_str(
+            "<rdf:Description rdf:about=\"\" xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\">\n\
+             <pdfaid:part>2</pdfaid:part>\n<pdfaid:conformance>B</pdfaid:conformance>\n\
+             </rdf:Description>\n",
+        );
+    }
+    packet.push_str("</rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>");
+    packet
+}
+
+/// Writes `metadata` to the Info dictionary and a matching XMP stream on the catalog.
+/// The producer defaults to stapler, both carry `created` as creation and modification date.
+pub fn apply_metadata(
+    document: &mut Document,
+    metadata: &DocumentMetadata,
+    created: Timestamp,
+    pdfa: bool,
+) {
+    let mut metadata = metadata.clone();
+    metadata
+        .producer
+        .get_or_insert_with(|| format!("stapler {}", env!("CARGO_PKG_VERSION")));
+
+    let mut info = Dictionary::new();
+    for (key, value) in metadata.entries() {
+        if let Some(value) = value {
+            info.set(key, text_string(value));
+        }
+    }
+    let date = Object::string_literal(created.to_pdf_date());
+    info.set("CreationDate", date.clone());
+    info.set("ModDate", date);
+    let info_id = document.add_object(Object::Dictionary(info));
+    document.trailer.set("Info", info_id);
+
+    // XMP stays readable for tools that scan files for metadata packets.
+    let xmp = Stream::new(
+        dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+        xmp_packet(&metadata, created, pdfa).into_bytes(),
+    )
+    .with_compression(false);
+    let xmp_id = document.add_object(xmp);
+    if let Ok(catalog) = document.catalog_mut() {
+        catalog.set("Metadata", xmp_id);
+    }
+}
@@ -1,39 +1,39 @@
+pub mod attachments;
+pub mod blank;
+pub mod compression;
+pub mod crop;
+pub mod dedup;
+pub mod image_pages;
+pub mod images;
+pub mod imposition;
+pub mod linearize;
 pub mod loader;
+pub mod metadata;
+pub mod options;
+pub mod page_size;
+pub mod pdfa;
+pub mod prune;
+pub mod save;
 #[cfg(test)]
 pub mod tests;
+pub mod text_pages;
+pub mod version;
+pub mod writer;
 use anyhow::{Context, Result};
-use loader::{
-    fs::{FileSystemMergingDestination, FileSystemMergingSource},
-    MergableDocument,
-};
+use attachments::OriginalFile;
+use loader::MergableDocument;
 use lopdf::{Bookmark, Document, Object, ObjectId};
 use std::collections::BTreeMap;
 
-#[derive(Debug, Clone)]
-pub struct FileSystemOptions<'a> {
-    pub input_sources: Vec<FileSystemMergingSource<'a>>,
-    pub destination: FileSystemMergingDestination<'a>,
-    pub compress: bool,
-}
-
-impl<'a> From<(&'a Vec<String>, &'a String, bool)> for FileSystemOptions<'a> {
-    fn from((input_files, output_file, compress): (&'a Vec<String>, &'a String, bool)) -> Self {
-        FileSystemOptions {
-            input_sources: input_files
-                .iter()
-                .map(|input_file| FileSystemMergingSource { input_file })
-                .collect(),
-            destination: FileSystemMergingDestination { output_file },
-            compress,
-        }
-    }
-}
+pub use options::StaplerOptions;
 
 fn update_document_hierarchy(
     document: &mut Document,
     root_page: (ObjectId, Object),
     catalog_object: (ObjectId, Object),
     pages: BTreeMap<ObjectId, Object>,
+    mut embedded_files: Vec<(Vec<u8>, Object)>,
+    original_files: Vec<OriginalFile>,
 ) -> Result<()> {
     let root_page_dictionary = {
         let mut dictionary = root_page
@@ -55,6 +55,18 @@ fn update_document_hierarchy(
         .objects
         .insert(root_page.0, Object::Dictionary(root_page_dictionary));
 
+    if !original_files.is_empty() {
+        // Objects were inserted by id, so find the first free one before adding more.
+        document.max_id = document
+            .objects
+            .keys()
+            .chain([&catalog_object.0])
+            .map(|(id, _)| *id)
+            .max()
+            .unwrap_or(0);
+        embedded_files.extend(attachments::embed_original_files(document, original_files));
+    }
+
     let catalog_dictionary = {
         let mut dictionary = catalog_object
             .1
@@ -63,6 +75,9 @@ fn update_document_hierarchy(
             .clone();
         dictionary.set("Pages", root_page.0);
         dictionary.remove(b"Outlines");
+        // Only the first catalog survives, so the attachments of all inputs, and the
+        // inputs themselves if asked for, move into it.
+        attachments::set_embedded_files(document, &mut dictionary, embedded_files);
         dictionary
     };
     document
@@ -74,8 +89,9 @@ fn update_document_hierarchy(
     document.renumber_objects();
     document.adjust_zero_pages();
 
+    // Renumbering may have moved the catalog, so look it up through the trailer again.
     if let Some(n) = document.build_outline() {
-        if let Ok(Object::Dictionary(ref mut dict)) = document.get_object_mut(catalog_object.0) {
+        if let Ok(dict) = document.catalog_mut() {
             dict.set("Outlines", Object::Reference(n));
         } else {
             anyhow::bail!("Could not get mutable dictionary from catalog object");
@@ -132,6 +148,7 @@ fn process_documents_objects(
 fn insert_pages(
     document: &mut Document,
     pages: &BTreeMap<ObjectId, Object>,
+    rotations: &BTreeMap<ObjectId, i64>,
     parent: ObjectId,
 ) -> Result<()> {
     for (object_id, object) in pages {
@@ -141,6 +158,9 @@ fn insert_pages(
                 .context("Could not get dictionary from page object.")?
                 .clone();
             dict.set("Parent", Object::Reference(parent));
+            if let Some(rotation) = rotations.get(object_id) {
+                dict.set("Rotate", *rotation);
+            }
             dict
         };
         document
@@ -151,29 +171,43 @@ fn insert_pages(
     Ok(())
 }
 
-fn add_bookmarks(doc: &mut Document, bookmarks: &BTreeMap<Option<u32>, Bookmark>) {
-    for (reference, bookmark) in bookmarks {
-        doc.add_bookmark(bookmark.clone(), *reference);
+fn add_bookmarks(doc: &mut Document, bookmarks: &[Bookmark]) {
+    for bookmark in bookmarks {
+        doc.add_bookmark(bookmark.clone(), None);
     }
 }
 
-pub fn merge_documents(input_docs: Vec<MergableDocument>, compress: bool) -> Result<Document> {
+pub fn merge_documents(input_docs: Vec<MergableDocument>) -> Result<Document> {
     anyhow::ensure!(
         input_docs.len() >= 2,
         "At least two documents are required to merge."
     );
+    combine_documents(input_docs)
+}
+
+/// [`merge_documents`] for any number of documents, since leaving out blank inputs may
+/// leave a single one.
+pub(crate) fn combine_documents(input_docs: Vec<MergableDocument>) -> Result<Document> {
+    anyhow::ensure!(!input_docs.is_empty(), "No document to merge.");
 
     let mut pages_map = BTreeMap::new();
+    let mut rotations = BTreeMap::new();
     let mut objects_map = BTreeMap::new();
-    let mut bookmarks_map = BTreeMap::new();
+    let mut bookmarks = Vec::new();
+    let mut embedded_files = Vec::new();
+    let mut original_files = Vec::new();
     let mut max_id: u32 = 1;
 
-    let mut result_doc = Document::with_version("1.5");
+    // A placeholder, the output version is chosen by `version::select_output_version`.
+    let mut result_doc = Document::with_version("1.4");
 
     for mut doc in input_docs {
         let first_page_id = doc.renumber(max_id).get_first_page_id();
-        bookmarks_map.insert(None, doc.get_filename_based_bookmark(first_page_id));
+        bookmarks.push(doc.get_filename_based_bookmark(first_page_id));
         pages_map.extend(doc.get_pages());
+        rotations.extend(doc.get_page_rotations());
+        embedded_files.extend(doc.get_embedded_files());
+        original_files.extend(doc.take_original_file());
         objects_map.extend(doc.get_objects());
         max_id = doc.get_max_id() + 1;
     }
@@ -183,18 +217,16 @@ pub fn merge_documents(input_docs: Vec<MergableDocument>, compress: bool) -> Res
         root_page_object,
     } = process_documents_objects(&mut result_doc, objects_map)?;
 
-    add_bookmarks(&mut result_doc, &bookmarks_map);
-    insert_pages(&mut result_doc, &pages_map, root_page_object.0)?;
+    add_bookmarks(&mut result_doc, &bookmarks);
+    insert_pages(&mut result_doc, &pages_map, &rotations, root_page_object.0)?;
     update_document_hierarchy(
         &mut result_doc,
         root_page_object,
         root_catalog_object,
         pages_map,
+        embedded_files,
+        original_files,
     )?;
 
-    if compress {
-        result_doc.compress();
-    }
-
     Ok(result_doc)
 }
@@ -0,0 +1,321 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use super::blank::BlankPageOptions;
+use super::compression::{CompressionLevel, CompressionOptions};
+use super::crop::PageMargins;
+use super::images::ImageOptions;
+use super::imposition::Imposition;
+use super::loader::fs::{FileSystemMergingDestination, FileSystemMergingSource};
+use super::metadata::DocumentMetadata;
+use super::page_size::PageSizeOptions;
+use super::save::OutputLayout;
+use super::version::PdfVersion;
+
+#[derive(Debug, Clone, Default)]
+pub struct StaplerOptions {
+    pub input_sources: Vec<FileSystemMergingSource>,
+    pub destination: FileSystemMergingDestination,
+    pub metadata: DocumentMetadata,
+    pub compression: CompressionOptions,
+    /// Collapse identical objects (fonts, images, ...) shared by several inputs.
+    pub deduplicate: bool,
+    /// Drop objects that are not reachable from the output's trailer.
+    pub prune: bool,
+    /// Embed every input file, as it was read, as an attachment of the output.
+    pub attach_inputs: bool,
+    /// Leave out pages of the inputs that show nothing, `None` keeps every page.
+    pub blank_pages: Option<BlankPageOptions>,
+    /// White space added around every page, before it is scaled onto `page_size`.
+    pub margins: Option<PageMargins>,
+    /// Scale every page onto one size, `None` keeps the sizes of the inputs.
+    pub page_size: Option<PageSizeOptions>,
+    /// Place several pages on each output sheet, `None` keeps one page per sheet.
+    pub imposition: Option<Imposition>,
+    /// Downsample and recompress images after merging, `None` leaves them untouched.
+    pub images: Option<ImageOptions>,
+    pub layout: OutputLayout,
+    /// Version written to the output, `None` uses the highest version of the inputs.
+    pub pdf_version: Option<PdfVersion>,
+    /// Write PDF/A-2b, failing when an input keeps the result from conforming.
+    pub pdfa: bool,
+    /// Number of threads used to load inputs, `None` uses one per CPU.
+    pub jobs: Option<usize>,
+}
+
+impl From<(&Vec<String>, &String, bool)> for StaplerOptions {
+    fn from((input_files, output_file, compress): (&Vec<String>, &String, bool)) -> Self {
+        StaplerOptions {
+            input_sources: input_files
+                .iter()
+                .map(|input_file| FileSystemMergingSource::new(input_file))
+                .collect(),
+            destination: FileSystemMergingDestination {
+                output_file: output_file.clone(),
+            },
+            compression: if compress {
+                CompressionOptions::compressed(CompressionLevel::Default)
+            } else {
+                CompressionOptions::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// An inclusive, 1-based range of pages. A missing end means "up to the last page".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageRange {
+    pub start: u32,
+    pub end: Option<u32>,
+}
+
+impl PageRange {
+    pub fn contains(&self, page: u32) -> bool {
+        page >= self.start && self.end.is_none_or(|end| page <= end)
+    }
+
+    pub fn validate(&self, page_count: u32) -> Result<()> {
+        let end = self.end.unwrap_or(self.start);
+        anyhow::ensure!(
+            self.start <= page_count && end <= page_count,
+            "page range '{}' exceeds the document's {} page(s)",
+            self,
+            page_count
+        );
+        Ok(())
+    }
+}
+
+impl fmt::Display for PageRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.end {
+            Some(end) if end == self.start => write!(f, "{}", self.start),
+            Some(end) => write!(f, "{}-{}", self.start, end),
+            None => write!(f, "{}-", self.start),
+        }
+    }
+}
+
+impl FromStr for PageRange {
+    type Err = anyhow::Error;
+
+    fn from_str(range: &str) -> Result<Self> {
+        let parse_page = |page: &str| -> Result<u32> {
+            let page = page
+                .trim()
+                .parse::<u32>()
+                .with_context(|| format!("'{}' is not a page number", page.trim()))?;
+            anyhow::ensure!(page > 0, "page numbers start at 1");
+            Ok(page)
+        };
+
+        let page_range = match range.split_once('-') {
+            Some((start, end)) if end.trim().is_empty() => PageRange {
+                start: parse_page(start)?,
+                end: None,
+            },
+            Some((start, end)) => PageRange {
+                start: parse_page(start)?,
+                end: Some(parse_page(end)?),
+            },
+            None => {
+                let page = parse_page(range)?;
+                PageRange {
+                    start: page,
+                    end: Some(page),
+                }
+            }
+        };
+
+        if let Some(end) = page_range.end {
+            anyhow::ensure!(
+                end >= page_range.start,
+                "range ends before it starts ({} > {})",
+                page_range.start,
+                end
+            );
+        }
+
+        Ok(page_range)
+    }
+}
+
+/// A selection of pages such as `1-3,5,8-`. Selected pages keep their document order.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PageRanges(Vec<PageRange>);
+
+impl PageRanges {
+    pub fn contains(&self, page: u32) -> bool {
+        self.0.iter().any(|range| range.contains(page))
+    }
+
+    pub fn validate(&self, page_count: u32) -> Result<()> {
+        self.0.iter().try_for_each(|range| range.validate(page_count))
+    }
+}
+
+impl FromStr for PageRanges {
+    type Err = anyhow::Error;
+
+    fn from_str(ranges: &str) -> Result<Self> {
+        let ranges = ranges
+            .split(',')
+            .map(|range| {
+                range
+                    .parse::<PageRange>()
+                    .with_context(|| format!("Invalid page range '{}'", range.trim()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(PageRanges(ranges))
+    }
+}
+
+impl TryFrom<String> for PageRanges {
+    type Error = String;
+
+    fn try_from(ranges: String) -> std::result::Result<Self, Self::Error> {
+        ranges.parse().map_err(|error| format!("{:#}", error))
+    }
+}
+
+/// A clockwise rotation applied on top of a page's existing `Rotate` entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "i64")]
+pub enum Rotation {
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+impl Rotation {
+    pub fn degrees(&self) -> i64 {
+        match self {
+            Rotation::Clockwise90 => 90,
+            Rotation::Clockwise180 => 180,
+            Rotation::Clockwise270 => 270,
+        }
+    }
+}
+
+impl TryFrom<i64> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: i64) -> std::result::Result<Self, Self::Error> {
+        match degrees.rem_euclid(360) {
+            90 => Ok(Rotation::Clockwise90),
+            180 => Ok(Rotation::Clockwise180),
+            270 => Ok(Rotation::Clockwise270),
+            _ => Err(format!(
+                "rotation must be one of 90, 180 or 270 degrees, got {}",
+                degrees
+            )),
+        }
+    }
+}
+
+/// What happens to the pages a rotation rule covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageRotation {
+    /// Turn clockwise on top of the page's existing rotation.
+    Clockwise(Rotation),
+    /// Turn landscape pages 90 degrees clockwise so they read as portrait.
+    Auto,
+}
+
+impl FromStr for PageRotation {
+    type Err = anyhow::Error;
+
+    fn from_str(rotation: &str) -> Result<Self> {
+        let rotation = rotation.trim();
+        if rotation.eq_ignore_ascii_case("auto") {
+            return Ok(PageRotation::Auto);
+        }
+        let degrees = rotation
+            .parse::<i64>()
+            .with_context(|| format!("'{}' is not a rotation, expected 90, 180, 270 or auto", rotation))?;
+        Ok(PageRotation::Clockwise(
+            Rotation::try_from(degrees).map_err(anyhow::Error::msg)?,
+        ))
+    }
+}
+
+/// Rotations for the pages of one input such as `90`, `auto` or `1-3:90,7-:auto`.
+/// A rule without a range covers every page, later rules win where rules overlap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageRotations(Vec<(Option<PageRange>, PageRotation)>);
+
+impl PageRotations {
+    pub fn for_page(&self, page: u32) -> Option<PageRotation> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(range, _)| range.is_none_or(|range| range.contains(page)))
+            .map(|(_, rotation)| *rotation)
+    }
+
+    pub fn validate(&self, page_count: u32) -> Result<()> {
+        self.0
+            .iter()
+            .filter_map(|(range, _)| range.as_ref())
+            .try_for_each(|range| range.validate(page_count))
+    }
+}
+
+impl From<Rotation> for PageRotations {
+    fn from(rotation: Rotation) -> Self {
+        PageRotations(vec![(None, PageRotation::Clockwise(rotation))])
+    }
+}
+
+impl FromStr for PageRotations {
+    type Err = anyhow::Error;
+
+    fn from_str(rules: &str) -> Result<Self> {
+        let rules = rules
+            .split(',')
+            .map(|rule| {
+                let parse_rule = || -> Result<(Option<PageRange>, PageRotation)> {
+                    match rule.split_once(':') {
+                        Some((range, rotation)) => Ok((Some(range.parse()?), rotation.parse()?)),
+                        None => Ok((None, rule.parse()?)),
+                    }
+                };
+                parse_rule().with_context(|| format!("Invalid rotation '{}'", rule.trim()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(PageRotations(rules))
+    }
+}
+
+/// Accepts plain degrees (`90`) as well as rotation rules (`"auto"`, `"1-3:90"`).
+impl<'de> Deserialize<'de> for PageRotations {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct RotationsVisitor;
+
+        impl serde::de::Visitor<'_> for RotationsVisitor {
+            type Value = PageRotations;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("90, 180, 270, \"auto\" or rules such as \"1-3:90,5-:auto\"")
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, degrees: i64) -> std::result::Result<Self::Value, E> {
+                Rotation::try_from(degrees).map(PageRotations::from).map_err(E::custom)
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, degrees: u64) -> std::result::Result<Self::Value, E> {
+                self.visit_i64(i64::try_from(degrees).map_err(E::custom)?)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, rules: &str) -> std::result::Result<Self::Value, E> {
+                rules.parse().map_err(|error| E::custom(format!("{:#}", error)))
+            }
+        }
+
+        deserializer.deserialize_any(RotationsVisitor)
+    }
+}
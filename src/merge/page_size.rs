@@ -0,0 +1,272 @@
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use lopdf::{Document, Object, ObjectId, Stream};
+use serde::Deserialize;
+
+use super::loader::{get_inherited_attribute, get_page_box};
+
+pub(crate) const POINTS_PER_INCH: f32 = 72.0;
+const POINTS_PER_MM: f32 = POINTS_PER_INCH / 25.4;
+/// Pages within this many points of the target are left as they are.
+const SIZE_TOLERANCE: f32 = 1.0;
+
+/// Size of the output pages in points, given in portrait orientation.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PageSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl PageSize {
+    pub const A4: PageSize = PageSize {
+        width: 595.276,
+        height: 841.89,
+    };
+    pub const LETTER: PageSize = PageSize {
+        width: 612.0,
+        height: 792.0,
+    };
+}
+
+impl FromStr for PageSize {
+    type Err = anyhow::Error;
+
+    /// Parses `a3`, `a4`, `a5`, `letter`, `legal` or a custom `WIDTHxHEIGHT` with an
+    /// optional `pt`, `mm` or `in` unit, e.g. `210x297mm` or `4x6in`.
+    fn from_str(size: &str) -> Result<Self> {
+        let size = size.trim().to_lowercase();
+        let (width, height) = match size.as_str() {
+            "a3" => return Ok(PageSize { width: 841.89, height: 1190.55 }),
+            "a4" => return Ok(PageSize::A4),
+            "a5" => return Ok(PageSize { width: 419.53, height: 595.276 }),
+            "letter" => return Ok(PageSize::LETTER),
+            "legal" => return Ok(PageSize { width: 612.0, height: 1008.0 }),
+            custom => custom.split_once('x').with_context(|| {
+                format!(
+                    "'{}' is not a page size, expected a4, a3, a5, letter, legal or WIDTHxHEIGHT[pt|mm|in]",
+                    custom
+                )
+            })?,
+        };
+        let (height, unit) = split_unit(height);
+        let parse = |value: &str| -> Result<f32> {
+            let value = value
+                .trim()
+                .parse::<f32>()
+                .with_context(|| format!("'{}' is not a page dimension", value.trim()))?;
+            anyhow::ensure!(value > 0.0, "page dimensions must be positive");
+            Ok(value * unit)
+        };
+        let (width, height) = (parse(width)?, parse(height)?);
+        Ok(PageSize {
+            width: width.min(height),
+            height: width.max(height),
+        })
+    }
+}
+
+/// Splits a trailing `pt`, `mm` or `in` off `value`, returning points per unit.
+pub(crate) fn split_unit(value: &str) -> (&str, f32) {
+    let value = value.trim();
+    match value {
+        value if value.ends_with("mm") => (&value[..value.len() - 2], POINTS_PER_MM),
+        value if value.ends_with("in") => (&value[..value.len() - 2], POINTS_PER_INCH),
+        value if value.ends_with("pt") => (&value[..value.len() - 2], 1.0),
+        value => (value, 1.0),
+    }
+}
+
+/// Parses a length such as `12`, `12pt`, `5mm` or `0.5in` into points.
+pub(crate) fn parse_length(length: &str) -> Result<f32> {
+    let (value, unit) = split_unit(length);
+    let value = value
+        .trim()
+        .parse::<f32>()
+        .with_context(|| format!("'{}' is not a length, expected e.g. 12pt, 5mm or 0.5in", length.trim()))?;
+    anyhow::ensure!(value >= 0.0, "lengths must not be negative");
+    Ok(value * unit)
+}
+
+impl TryFrom<String> for PageSize {
+    type Error = String;
+
+    fn try_from(size: String) -> std::result::Result<Self, Self::Error> {
+        size.parse().map_err(|error| format!("{:#}", error))
+    }
+}
+
+/// How a page is scaled onto the target size. Either way it is centered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageFit {
+    /// Scale until the whole page fits, leaving white bars on two sides.
+    #[default]
+    Fit,
+    /// Scale until the target is covered, cutting off what sticks out.
+    Fill,
+}
+
+impl FromStr for PageFit {
+    type Err = anyhow::Error;
+
+    fn from_str(fit: &str) -> Result<Self> {
+        match fit.trim().to_lowercase().as_str() {
+            "fit" => Ok(PageFit::Fit),
+            "fill" => Ok(PageFit::Fill),
+            other => anyhow::bail!("'{}' is not a page fit, expected fit or fill", other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageSizeOptions {
+    pub size: PageSize,
+    pub fit: PageFit,
+}
+
+pub(crate) fn format_number(value: f32) -> String {
+    let formatted = format!("{:.4}", value);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Scale and translation that put `page_box` onto a `width` x `height` page.
+fn placement(page_box: [f32; 4], width: f32, height: f32, fit: PageFit) -> [f32; 3] {
+    let [left, bottom, right, top] = page_box;
+    let (box_width, box_height) = (right - left, top - bottom);
+    let (scale_x, scale_y) = (width / box_width, height / box_height);
+    let scale = match fit {
+        PageFit::Fit => scale_x.min(scale_y),
+        PageFit::Fill => scale_x.max(scale_y),
+    };
+    [
+        scale,
+        (width - box_width * scale) / 2.0 - left * scale,
+        (height - box_height * scale) / 2.0 - bottom * scale,
+    ]
+}
+
+fn transform_rect(rect: &[Object], [scale, x, y]: [f32; 3]) -> Option<Vec<Object>> {
+    let values = rect
+        .iter()
+        .map(|value| value.as_float().ok())
+        .collect::<Option<Vec<f32>>>()?;
+    let &[x1, y1, x2, y2] = values.as_slice() else {
+        return None;
+    };
+    Some(
+        [x1 * scale + x, y1 * scale + y, x2 * scale + x, y2 * scale + y]
+            .into_iter()
+            .map(Object::Real)
+            .collect(),
+    )
+}
+
+/// Puts `prefix` in front of the page's content streams, restoring the graphics state
+/// after them.
+pub(crate) fn wrap_page_contents(document: &mut Document, page_id: ObjectId, prefix: String) {
+    let prefix_id = document.add_object(Stream::new(Default::default(), prefix.into_bytes()));
+    let suffix_id = document.add_object(Stream::new(Default::default(), b"\nQ".to_vec()));
+    let Ok(page) = document.get_dictionary_mut(page_id) else {
+        return;
+    };
+    let mut contents = vec![Object::Reference(prefix_id)];
+    match page.get(b"Contents") {
+        Ok(Object::Array(streams)) => contents.extend(streams.iter().cloned()),
+        Ok(stream) => contents.push(stream.clone()),
+        Err(_) => {}
+    }
+    contents.push(Object::Reference(suffix_id));
+    page.set("Contents", contents);
+}
+
+fn normalize_page(document: &mut Document, page_id: ObjectId, options: &PageSizeOptions) {
+    let Some(page_box) = get_page_box(document, page_id) else {
+        return;
+    };
+    let [left, bottom, right, top] = page_box;
+    let (box_width, box_height) = (right - left, top - bottom);
+    if box_width <= 0.0 || box_height <= 0.0 {
+        return;
+    }
+    // Landscape pages stay landscape, the target is turned to match.
+    let (width, height) = if box_width > box_height {
+        (options.size.height, options.size.width)
+    } else {
+        (options.size.width, options.size.height)
+    };
+    let already_normalized = left.abs() < SIZE_TOLERANCE
+        && bottom.abs() < SIZE_TOLERANCE
+        && (box_width - width).abs() < SIZE_TOLERANCE
+        && (box_height - height).abs() < SIZE_TOLERANCE;
+    if already_normalized {
+        return;
+    }
+
+    let transform = placement(page_box, width, height, options.fit);
+    let [scale, x, y] = transform;
+    // The clip keeps content outside the old crop box hidden, as it was before.
+    let prefix = format!(
+        "q {scale} 0 0 {scale} {x} {y} cm {left} {bottom} {box_width} {box_height} re W n\n",
+        scale = format_number(scale),
+        x = format_number(x),
+        y = format_number(y),
+        left = format_number(left),
+        bottom = format_number(bottom),
+        box_width = format_number(box_width),
+        box_height = format_number(box_height),
+    );
+    wrap_page_contents(document, page_id, prefix);
+
+    let annotation_ids = document
+        .get_dictionary(page_id)
+        .ok()
+        .and_then(|page| page.get(b"Annots").ok())
+        .and_then(|annotations| match annotations {
+            Object::Array(annotations) => Some(annotations.clone()),
+            Object::Reference(id) => document.get_object(*id).and_then(Object::as_array).ok().cloned(),
+            _ => None,
+        })
+        .unwrap_or_default();
+    for annotation_id in annotation_ids.iter().filter_map(|id| id.as_reference().ok()) {
+        if let Ok(annotation) = document.get_dictionary_mut(annotation_id) {
+            let rect = annotation
+                .get(b"Rect")
+                .and_then(Object::as_array)
+                .ok()
+                .and_then(|rect| transform_rect(rect, transform));
+            if let Some(rect) = rect {
+                annotation.set("Rect", rect);
+            }
+        }
+    }
+
+    // Resources are copied down, the page gets boxes of its own that no longer match
+    // whatever its parent carried.
+    let resources = get_inherited_attribute(document, page_id, b"Resources");
+    let Ok(page) = document.get_dictionary_mut(page_id) else {
+        return;
+    };
+    if let Some(resources) = resources {
+        page.set("Resources", resources);
+    }
+    page.set(
+        "MediaBox",
+        vec![0.into(), 0.into(), Object::Real(width), Object::Real(height)],
+    );
+    for key in [b"CropBox".as_slice(), b"BleedBox", b"TrimBox", b"ArtBox"] {
+        page.remove(key);
+    }
+}
+
+/// Scales and centers every page onto the configured size, rewriting its boxes.
+pub fn normalize_page_sizes(document: &mut Document, options: &PageSizeOptions) {
+    let page_ids = document.get_pages().into_values().collect::<Vec<_>>();
+    for page_id in page_ids {
+        normalize_page(document, page_id, options);
+    }
+}
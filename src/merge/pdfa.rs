@@ -0,0 +1,321 @@
+use std::collections::{BTreeSet, HashSet};
+
+use anyhow::{Context, Result};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use md5::{Digest, Md5};
+use moxcms::ColorProfile;
+
+use super::attachments::{attachment_name, embedded_files, read_attachment};
+
+/// Action types PDF/A-2 does not allow, JavaScript being the one that matters in practice.
+const FORBIDDEN_ACTIONS: [&[u8]; 11] = [
+    b"JavaScript",
+    b"Launch",
+    b"Sound",
+    b"Movie",
+    b"ResetForm",
+    b"ImportData",
+    b"Hide",
+    b"SetOCGState",
+    b"Rendition",
+    b"Trans",
+    b"GoTo3DView",
+];
+const MAX_ACTION_CHAIN: usize = 8;
+const SRGB_IDENTIFIER: &str = "sRGB IEC61966-2.1";
+
+/// Something that keeps the document from conforming to PDF/A-2b.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PdfaViolation {
+    /// 1-based page of the merged document, `None` for document-wide problems.
+    pub page: Option<usize>,
+    pub reason: String,
+}
+
+fn dictionary_of<'a>(document: &'a Document, object: &'a Object) -> Option<&'a Dictionary> {
+    document
+        .dereference(object)
+        .ok()
+        .and_then(|(_, object)| match object {
+            Object::Stream(stream) => Some(&stream.dict),
+            object => object.as_dict().ok(),
+        })
+}
+
+/// The first forbidden action type in the chain starting at `action`.
+fn forbidden_action(document: &Document, action: &Object, depth: usize) -> Option<String> {
+    if depth > MAX_ACTION_CHAIN {
+        return None;
+    }
+    let action = dictionary_of(document, action)?;
+    if let Ok(kind) = action.get(b"S").and_then(Object::as_name) {
+        if FORBIDDEN_ACTIONS.contains(&kind) {
+            return Some(String::from_utf8_lossy(kind).to_string());
+        }
+    }
+    match action.get(b"Next") {
+        Ok(Object::Array(actions)) => actions
+            .iter()
+            .find_map(|next| forbidden_action(document, next, depth + 1)),
+        Ok(next) => forbidden_action(document, next, depth + 1),
+        Err(_) => None,
+    }
+}
+
+fn font_is_embedded(document: &Document, font: &Dictionary) -> bool {
+    let descriptor_owner = match font.get(b"Subtype").and_then(Object::as_name) {
+        // Type 3 glyphs are content streams of the font itself.
+        Ok(b"Type3") => return true,
+        Ok(b"Type0") => font
+            .get(b"DescendantFonts")
+            .ok()
+            .and_then(|fonts| document.dereference(fonts).ok())
+            .and_then(|(_, fonts)| fonts.as_array().ok())
+            .and_then(|fonts| fonts.first())
+            .and_then(|font| dictionary_of(document, font)),
+        _ => Some(font),
+    };
+    descriptor_owner
+        .and_then(|font| font.get(b"FontDescriptor").ok())
+        .and_then(|descriptor| dictionary_of(document, descriptor))
+        .is_some_and(|descriptor| {
+            [b"FontFile".as_slice(), b"FontFile2", b"FontFile3"]
+                .iter()
+                .any(|key| descriptor.has(key))
+        })
+}
+
+/// Collects fonts that are not embedded, following form XObjects into their own resources.
+fn check_resources(
+    document: &Document,
+    resources: &Dictionary,
+    visited: &mut HashSet<ObjectId>,
+    reasons: &mut BTreeSet<String>,
+) {
+    let entries = |key: &[u8]| {
+        resources
+            .get(key)
+            .ok()
+            .and_then(|entries| dictionary_of(document, entries))
+            .map(|entries| entries.iter().map(|(_, value)| value).collect::<Vec<_>>())
+            .unwrap_or_default()
+    };
+
+    for font in entries(b"Font") {
+        if let Some(font) = dictionary_of(document, font) {
+            if !font_is_embedded(document, font) {
+                let name = font
+                    .get(b"BaseFont")
+                    .and_then(Object::as_name)
+                    .map_or("unnamed font".to_string(), |name| {
+                        String::from_utf8_lossy(name).to_string()
+                    });
+                reasons.insert(format!("font {} is not embedded", name));
+            }
+        }
+    }
+    for xobject in entries(b"XObject") {
+        if let Ok(id) = xobject.as_reference() {
+            check_form(document, id, visited, reasons);
+        }
+    }
+}
+
+fn check_form(
+    document: &Document,
+    form_id: ObjectId,
+    visited: &mut HashSet<ObjectId>,
+    reasons: &mut BTreeSet<String>,
+) {
+    if !visited.insert(form_id) {
+        return;
+    }
+    let Ok(Object::Stream(form)) = document.get_object(form_id) else {
+        return;
+    };
+    if !matches!(form.dict.get(b"Subtype").and_then(Object::as_name), Ok(b"Form")) {
+        return;
+    }
+    if let Some(resources) = form
+        .dict
+        .get(b"Resources")
+        .ok()
+        .and_then(|resources| dictionary_of(document, resources))
+    {
+        check_resources(document, resources, visited, reasons);
+    }
+}
+
+fn check_annotation(
+    document: &Document,
+    annotation: &Dictionary,
+    visited: &mut HashSet<ObjectId>,
+    reasons: &mut BTreeSet<String>,
+) {
+    if let Some(action) = annotation
+        .get(b"A")
+        .ok()
+        .and_then(|action| forbidden_action(document, action, 0))
+    {
+        reasons.insert(format!("an annotation uses a {} action", action));
+    }
+    if annotation.has(b"AA") {
+        reasons.insert("an annotation has additional actions".to_string());
+    }
+
+    // Normal appearances are either a form or a dictionary of forms per state.
+    let appearance = annotation
+        .get(b"AP")
+        .ok()
+        .and_then(|appearances| dictionary_of(document, appearances))
+        .and_then(|appearances| appearances.get(b"N").ok());
+    match appearance {
+        Some(Object::Reference(id)) => match document.get_object(*id) {
+            Ok(Object::Dictionary(states)) => {
+                for (_, state) in states {
+                    if let Ok(id) = state.as_reference() {
+                        check_form(document, id, visited, reasons);
+                    }
+                }
+            }
+            _ => check_form(document, *id, visited, reasons),
+        },
+        Some(Object::Dictionary(states)) => {
+            for (_, state) in states {
+                if let Ok(id) = state.as_reference() {
+                    check_form(document, id, visited, reasons);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn check_page(document: &Document, page_id: ObjectId) -> BTreeSet<String> {
+    let mut reasons = BTreeSet::new();
+    let mut visited = HashSet::new();
+    if let Ok(page) = document.get_dictionary(page_id) {
+        if page.has(b"AA") {
+            reasons.insert("the page has additional actions".to_string());
+        }
+    }
+    if let Ok((resources, resource_ids)) = document.get_page_resources(page_id) {
+        if let Some(resources) = resources {
+            check_resources(document, resources, &mut visited, &mut reasons);
+        }
+        for id in resource_ids {
+            if let Ok(resources) = document.get_dictionary(id) {
+                check_resources(document, resources, &mut visited, &mut reasons);
+            }
+        }
+    }
+    for annotation in document.get_page_annotations(page_id).unwrap_or_default() {
+        check_annotation(document, annotation, &mut visited, &mut reasons);
+    }
+    reasons
+}
+
+/// Checks the requirements of PDF/A-2b stapler cannot fix on its own: no JavaScript or
+/// other forbidden actions, embedded fonts, and only PDFs as attachments. This is not a
+/// full validator, color spaces and transparency are taken as they are.
+pub fn find_pdfa_violations(document: &Document) -> Vec<PdfaViolation> {
+    let mut violations = Vec::new();
+    let mut document_violation = |reason: &str| {
+        violations.push(PdfaViolation {
+            page: None,
+            reason: reason.to_string(),
+        })
+    };
+
+    if let Ok(catalog) = document.catalog() {
+        let has_javascript = catalog
+            .get(b"Names")
+            .ok()
+            .and_then(|names| dictionary_of(document, names))
+            .is_some_and(|names| names.has(b"JavaScript"));
+        if has_javascript {
+            document_violation("the document contains document-level JavaScript");
+        }
+        if let Some(action) = catalog
+            .get(b"OpenAction")
+            .ok()
+            .and_then(|action| forbidden_action(document, action, 0))
+        {
+            document_violation(&format!("the document opens with a {} action", action));
+        }
+        if catalog.has(b"AA") {
+            document_violation("the document has additional actions");
+        }
+    }
+    // PDF/A-2 only allows PDF/A attachments. Whether an attached PDF conforms is not checked.
+    for (key, file) in embedded_files(document) {
+        match read_attachment(document, &key, &file) {
+            Ok(attachment) if !attachment.data.starts_with(b"%PDF-") => {
+                document_violation(&format!("the attachment {} is not a PDF file", attachment.file_name));
+            }
+            Ok(_) => {}
+            Err(_) => document_violation(&format!("the attachment {} cannot be read", attachment_name(&key))),
+        }
+    }
+
+    for (page_number, page_id) in document.get_pages() {
+        violations.extend(check_page(document, page_id).into_iter().map(|reason| {
+            PdfaViolation {
+                page: Some(page_number as usize),
+                reason,
+            }
+        }));
+    }
+    violations
+}
+
+/// An sRGB ICC profile for the output intent.
+fn srgb_profile() -> Result<Vec<u8>> {
+    let mut profile = ColorProfile::new_srgb();
+    // The CICP tag is ICC 4.4, older PDF/A validators reject tags they do not know.
+    profile.cicp = None;
+    profile
+        .encode()
+        .map_err(|error| anyhow::anyhow!("Cannot encode the sRGB output profile: {:?}", error))
+}
+
+/// Adds what PDF/A-2b requires on top of the metadata: an sRGB output intent and a
+/// file identifier. Fails when the output intent cannot be written, as the metadata
+/// already claims conformance.
+pub fn apply_pdfa(document: &mut Document) -> Result<()> {
+    let profile_id = document.add_object(Stream::new(dictionary! { "N" => 3 }, srgb_profile()?));
+    let intent_id = document.add_object(dictionary! {
+        "Type" => "OutputIntent",
+        "S" => "GTS_PDFA1",
+        "OutputConditionIdentifier" => Object::string_literal(SRGB_IDENTIFIER),
+        "Info" => Object::string_literal(SRGB_IDENTIFIER),
+        "RegistryName" => Object::string_literal("http://www.color.org"),
+        "DestOutputProfile" => profile_id,
+    });
+    document
+        .catalog_mut()
+        .context("Could not find the document catalog")?
+        .set("OutputIntents", vec![Object::Reference(intent_id)]);
+
+    // Both halves of a new file's identifier are the same, derived from its metadata.
+    let mut hasher = Md5::new();
+    if let Ok(metadata) = document
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Metadata"))
+        .and_then(Object::as_reference)
+        .and_then(|id| document.get_object(id))
+        .and_then(Object::as_stream)
+    {
+        hasher.update(&metadata.content);
+    }
+    hasher.update(document.objects.len().to_le_bytes());
+    let id = hasher.finalize().to_vec();
+    document.trailer.set(
+        "ID",
+        vec![
+            Object::String(id.clone(), StringFormat::Hexadecimal),
+            Object::String(id, StringFormat::Hexadecimal),
+        ],
+    );
+    Ok(())
+}
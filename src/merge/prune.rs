@@ -0,0 +1,67 @@
+use std::collections::HashSet;
+
+use lopdf::{Document, Object, ObjectId};
+
+use super::writer::RemovalSummary;
+
+fn collect_references(object: &Object, references: &mut Vec<ObjectId>) {
+    match object {
+        Object::Reference(id) => references.push(*id),
+        Object::Array(array) => array
+            .iter()
+            .for_each(|item| collect_references(item, references)),
+        Object::Dictionary(dictionary) => dictionary
+            .iter()
+            .for_each(|(_, value)| collect_references(value, references)),
+        Object::Stream(stream) => stream
+            .dict
+            .iter()
+            .for_each(|(_, value)| collect_references(value, references)),
+        _ => {}
+    }
+}
+
+/// Every object reachable from the trailer, each visited once.
+fn reachable_objects(document: &Document) -> HashSet<ObjectId> {
+    let mut reachable = HashSet::new();
+    let mut pending = Vec::new();
+    document
+        .trailer
+        .iter()
+        .for_each(|(_, value)| collect_references(value, &mut pending));
+    while let Some(id) = pending.pop() {
+        if reachable.insert(id) {
+            if let Some(object) = document.objects.get(&id) {
+                collect_references(object, &mut pending);
+            }
+        }
+    }
+    reachable
+}
+
+/// Drops every object that cannot be reached from the trailer (`Root`, `Info`, ...).
+///
+/// Merging copies the non-page objects of every input, including their Info
+/// dictionaries, orphaned outline items and resources no page uses anymore.
+pub fn prune_unreferenced_objects(document: &mut Document) -> RemovalSummary {
+    let reachable = reachable_objects(document);
+    let unreachable = document
+        .objects
+        .keys()
+        .filter(|id| !reachable.contains(id))
+        .copied()
+        .collect::<Vec<ObjectId>>();
+
+    let mut summary = RemovalSummary::default();
+    for id in unreachable {
+        if let Some(object) = document.objects.remove(&id) {
+            summary.record(id, &object);
+        }
+    }
+
+    if summary.objects_removed > 0 {
+        document.renumber_objects();
+    }
+
+    summary
+}
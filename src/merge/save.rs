@@ -0,0 +1,50 @@
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use lopdf::xref::XrefType;
+use lopdf::{Document, SaveOptions};
+
+use super::linearize::write_linearized;
+
+/// How the objects of the output file are laid out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputLayout {
+    /// Every object stands alone and is indexed by a classic cross-reference table.
+    #[default]
+    Classic,
+    /// Non-stream objects are packed into object streams indexed by a cross-reference
+    /// stream (PDF 1.5+).
+    ObjectStreams,
+    /// The catalog, the first page and hint tables come first, so viewers can display
+    /// the first page before the whole file is downloaded ("fast web view").
+    Linearized,
+}
+
+pub fn write_document<W: Write>(
+    document: &mut Document,
+    target: &mut W,
+    layout: OutputLayout,
+) -> Result<()> {
+    match layout {
+        OutputLayout::Classic => {
+            // lopdf defaults to cross-reference streams, which need PDF 1.5.
+            document.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+            document
+                .save_to(target)
+                .context("Failed to write PDF document")
+        }
+        OutputLayout::ObjectStreams => {
+            let save_options = SaveOptions::builder()
+                .use_object_streams(true)
+                .use_xref_streams(true)
+                .build();
+            document
+                .save_with_options(target, save_options)
+                .context("Failed to write PDF document")
+        }
+        // lopdf has no linearization support, so stapler lays these files out itself.
+        OutputLayout::Linearized => {
+            write_linearized(document, target).context("Failed to write linearized PDF document")
+        }
+    }
+}
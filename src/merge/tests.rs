@@ -1,8 +1,12 @@
 use lopdf::{ content::{ Content, Operation }, dictionary, Stream };
 
 use super::*;
+use compression::{CompressionOptions, StreamCompression};
 
-pub const COMPRESS_OUTPUT_WHEN_TESTING: bool = false;
+pub const COMPRESS_OUTPUT_WHEN_TESTING: CompressionOptions = CompressionOptions {
+    streams: StreamCompression::Unchanged,
+    keep_compressed_images: false,
+};
 
 // Function to create a sample PDF document
 pub fn create_sample_pdf(title: &str) -> Document {
@@ -73,17 +77,18 @@ pub fn create_sample_pdf(title: &str) -> Document {
 }
 
 #[allow(dead_code)]
-fn test_merge_x_documents(documents_num: u16, compress: bool) {
+fn test_merge_x_documents(documents_num: u16, compression: CompressionOptions) {
     let mut mergable_docs = vec![];
     for i in 0..documents_num {
         let doc = create_sample_pdf(&format!("Document {}", i));
         mergable_docs.push(MergableDocument::from_document(&format!("doc{}.pdf", i), doc));
     }
 
-    let result = merge_documents(mergable_docs, compress);
+    let result = merge_documents(mergable_docs);
 
     assert!(result.is_ok());
-    let merged_doc = result.unwrap();
+    let mut merged_doc = result.unwrap();
+    compression::apply_compression(&mut merged_doc, &compression);
 
     // Check if the merged document has the expected number of pages
     let num_pages = merged_doc.get_pages().len();
@@ -157,9 +162,1243 @@ fn test_merge_500_documents() {
 #[test]
 fn test_error_on_merge_empty_documents() {
     let mergable_docs = vec![];
-    let result = merge_documents(mergable_docs, COMPRESS_OUTPUT_WHEN_TESTING);
+    let result = merge_documents(mergable_docs);
 
     assert!(result.is_err());
     let error = result.err().unwrap();
     assert_eq!(error.to_string(), "At least two documents are required to merge.");
 }
+
+#[test]
+fn test_merge_keeps_a_bookmark_per_document() {
+    let mut mergable_docs = vec![];
+    for i in 0..3 {
+        let mut doc = MergableDocument::from_document(
+            &format!("doc{}.pdf", i),
+            create_sample_pdf(&format!("Document {}", i)),
+        );
+        doc.set_bookmark_title(&format!("Chapter {}", i));
+        mergable_docs.push(doc);
+    }
+
+    let merged_doc = merge_documents(mergable_docs).unwrap();
+
+    let titles: Vec<String> = merged_doc
+        .bookmarks
+        .iter()
+        .map(|id| merged_doc.bookmark_table[id].title.clone())
+        .collect();
+    assert_eq!(titles, vec!["Chapter 0", "Chapter 1", "Chapter 2"]);
+}
+
+#[test]
+fn test_merge_rotated_document() {
+    let mut rotated = MergableDocument::from_document("rotated.pdf", create_sample_pdf("Rotated"));
+    rotated.rotate_pages(&options::Rotation::Clockwise90.into()).unwrap();
+    let mergable_docs = vec![
+        MergableDocument::from_document("upright.pdf", create_sample_pdf("Upright")),
+        rotated,
+    ];
+
+    let merged_doc = merge_documents(mergable_docs).unwrap();
+
+    let rotations: Vec<i64> = merged_doc
+        .page_iter()
+        .map(|page_id| {
+            merged_doc
+                .get_dictionary(page_id)
+                .and_then(|page| page.get(b"Rotate"))
+                .and_then(Object::as_i64)
+                .unwrap_or(0)
+        })
+        .collect();
+    assert_eq!(rotations, vec![0, 90]);
+}
+
+#[test]
+fn test_deduplicate_shared_fonts_and_resources() {
+    let mergable_docs = (0..3)
+        .map(|i| {
+            MergableDocument::from_document(
+                &format!("doc{}.pdf", i),
+                create_sample_pdf(&format!("Document {}", i)),
+            )
+        })
+        .collect();
+    let mut merged_doc = merge_documents(mergable_docs).unwrap();
+    let object_count = merged_doc.objects.len();
+
+    let summary = dedup::deduplicate_objects(&mut merged_doc);
+
+    // Each extra input brings an identical font and, once fonts are shared, identical resources.
+    assert_eq!(summary.objects_removed, 4);
+    assert!(summary.bytes_saved > 0);
+    assert_eq!(merged_doc.objects.len(), object_count - 4);
+
+    let resources: Vec<ObjectId> = merged_doc
+        .page_iter()
+        .map(|page_id| {
+            merged_doc
+                .get_dictionary(page_id)
+                .and_then(|page| page.get(b"Resources"))
+                .and_then(Object::as_reference)
+                .unwrap()
+        })
+        .collect();
+    assert!(resources.windows(2).all(|ids| ids[0] == ids[1]));
+    for (index, page_id) in merged_doc.page_iter().enumerate() {
+        let content = String::from_utf8(merged_doc.get_page_content(page_id).unwrap()).unwrap();
+        assert!(content.contains(&format!("Document {}", index)));
+    }
+}
+
+#[test]
+fn test_deduplicate_keeps_annotations_and_fields_apart() {
+    let mergable_docs = (0..2)
+        .map(|i| {
+            let mut doc = create_sample_pdf(&format!("Document {}", i));
+            let signature_id = doc.add_object(dictionary! {
+                "Filter" => "Adobe.PPKLite",
+                "ByteRange" => vec![0.into(), 10.into(), 20.into(), 10.into()],
+                "Contents" => Object::String(vec![0; 8], lopdf::StringFormat::Hexadecimal),
+            });
+            let field_id = doc.add_object(dictionary! {
+                "FT" => "Sig",
+                "T" => Object::string_literal("Signature"),
+                "V" => signature_id,
+                "Subtype" => "Widget",
+                "Rect" => vec![0.into(), 0.into(), 100.into(), 20.into()],
+            });
+            let link_id = doc.add_object(dictionary! {
+                "Subtype" => "Link",
+                "Rect" => vec![0.into(), 0.into(), 10.into(), 10.into()],
+                "Border" => vec![0.into(), 0.into(), 0.into()],
+            });
+            let page_id = doc.page_iter().next().unwrap();
+            doc.get_dictionary_mut(page_id)
+                .unwrap()
+                .set("Annots", vec![Object::Reference(link_id), Object::Reference(field_id)]);
+            MergableDocument::from_document(&format!("doc{}.pdf", i), doc)
+        })
+        .collect();
+    let mut merged_doc = merge_documents(mergable_docs).unwrap();
+
+    let summary = dedup::deduplicate_objects(&mut merged_doc);
+
+    // Only the font and the resources are shared.
+    assert_eq!(summary.objects_removed, 2);
+    let annotations = merged_doc
+        .page_iter()
+        .map(|page_id| merged_doc.get_dictionary(page_id).unwrap().get(b"Annots").unwrap().clone())
+        .collect::<Vec<_>>();
+    assert_ne!(annotations[0], annotations[1]);
+    let signatures = merged_doc
+        .objects
+        .values()
+        .filter(|object| object.as_dict().is_ok_and(|dictionary| dictionary.has(b"ByteRange")))
+        .count();
+    assert_eq!(signatures, 2);
+}
+
+#[test]
+fn test_prune_drops_objects_of_dropped_input_structures() {
+    let mergable_docs = (0..3)
+        .map(|i| {
+            let mut doc = create_sample_pdf(&format!("Document {}", i));
+            let info_id = doc.add_object(dictionary! { "Title" => Object::string_literal("Input") });
+            doc.trailer.set("Info", info_id);
+            if i == 0 {
+                // Orphans referring to each other are dropped too.
+                let first_id = doc.new_object_id();
+                let second_id = doc.add_object(dictionary! { "Next" => first_id });
+                doc.objects.insert(first_id, Object::Dictionary(dictionary! { "Next" => second_id }));
+            }
+            MergableDocument::from_document(&format!("doc{}.pdf", i), doc)
+        })
+        .collect();
+    let mut merged_doc = merge_documents(mergable_docs).unwrap();
+    let object_count = merged_doc.objects.len();
+
+    let summary = prune::prune_unreferenced_objects(&mut merged_doc);
+
+    // The Info dictionaries of the inputs are copied but nothing refers to them anymore.
+    assert_eq!(summary.objects_removed, 5);
+    assert!(summary.bytes_saved > 0);
+    assert_eq!(merged_doc.objects.len(), object_count - 5);
+    assert_eq!(merged_doc.get_pages().len(), 3);
+    for (index, page_id) in merged_doc.page_iter().enumerate() {
+        let content = String::from_utf8(merged_doc.get_page_content(page_id).unwrap()).unwrap();
+        assert!(content.contains(&format!("Document {}", index)));
+    }
+}
+
+#[test]
+fn test_write_with_object_streams() {
+    let mergable_docs = (0..3)
+        .map(|i| {
+            MergableDocument::from_document(
+                &format!("doc{}.pdf", i),
+                create_sample_pdf(&format!("Document {}", i)),
+            )
+        })
+        .collect();
+    let mut merged_doc = merge_documents(mergable_docs).unwrap();
+    compression::apply_compression(
+        &mut merged_doc,
+        &CompressionOptions::compressed(compression::CompressionLevel::Default),
+    );
+    let mut classic = Vec::new();
+    let mut packed = Vec::new();
+
+    save::write_document(&mut merged_doc.clone(), &mut classic, save::OutputLayout::Classic).unwrap();
+    save::write_document(&mut merged_doc, &mut packed, save::OutputLayout::ObjectStreams).unwrap();
+
+    assert!(packed.len() < classic.len());
+    let classic_text = String::from_utf8_lossy(&classic);
+    assert!(classic_text.contains("\nxref\n") && classic_text.contains("trailer"));
+    assert!(!classic_text.contains("/Type /XRef") && !classic_text.contains("/Type/XRef"));
+    let packed_text = String::from_utf8_lossy(&packed);
+    assert!(packed_text.contains("/ObjStm") && packed_text.contains("/XRef"));
+    assert!(!packed_text.contains("\nxref\n"));
+
+    let reloaded = Document::load_mem(&packed).unwrap();
+    assert_eq!(reloaded.get_pages().len(), 3);
+    for (index, page_id) in reloaded.page_iter().enumerate() {
+        let content = String::from_utf8(reloaded.get_page_content(page_id).unwrap()).unwrap();
+        assert!(content.contains(&format!("Document {}", index)));
+    }
+}
+
+fn add_image(doc: &mut Document, name: &str, size: u32, shown_at: i64, pixel: impl Fn(u32, u32) -> [u8; 3]) -> ObjectId {
+    let pixels: Vec<u8> = (0..size * size)
+        .flat_map(|index| pixel(index % size, index / size))
+        .collect();
+    let mut image = Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Image",
+            "Width" => size,
+            "Height" => size,
+            "ColorSpace" => "DeviceRGB",
+            "BitsPerComponent" => 8,
+        },
+        pixels,
+    );
+    image.compress().unwrap();
+    let image_id = doc.add_object(image);
+
+    let page_id = doc.page_iter().next().unwrap();
+    let resources_id = doc
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"Resources"))
+        .and_then(Object::as_reference)
+        .unwrap();
+    let resources = doc.get_dictionary_mut(resources_id).unwrap();
+    if !resources.has(b"XObject") {
+        resources.set("XObject", dictionary! {});
+    }
+    resources
+        .get_mut(b"XObject")
+        .and_then(Object::as_dict_mut)
+        .unwrap()
+        .set(name, image_id);
+
+    let drawing = Content {
+        operations: vec![
+            Operation::new("q", vec![]),
+            Operation::new("cm", vec![shown_at.into(), 0.into(), 0.into(), shown_at.into(), 0.into(), 0.into()]),
+            Operation::new("Do", vec![Object::Name(name.as_bytes().to_vec())]),
+            Operation::new("Q", vec![]),
+        ],
+    };
+    let content_id = doc.add_object(Stream::new(dictionary! {}, drawing.encode().unwrap()));
+    let page = doc.get_dictionary_mut(page_id).unwrap();
+    let text_id = page.get(b"Contents").and_then(Object::as_reference).unwrap();
+    page.set("Contents", vec![text_id.into(), content_id.into()]);
+    image_id
+}
+
+#[test]
+fn test_optimize_images_downsamples_and_recompresses() {
+    let mut photo_doc = create_sample_pdf("Photo");
+    // 600 pixels over two inches are 300 DPI.
+    add_image(&mut photo_doc, "Im1", 600, 144, |x, y| {
+        [(x * 7 % 251) as u8, (y * 13 % 241) as u8, ((x + y) % 256) as u8]
+    });
+    let mut line_art_doc = create_sample_pdf("Line art");
+    add_image(&mut line_art_doc, "Im1", 64, 288, |x, y| {
+        if (x / 8 + y / 8) % 2 == 0 { [0, 0, 0] } else { [255, 255, 255] }
+    });
+    let mergable_docs = vec![
+        MergableDocument::from_document("photo.pdf", photo_doc),
+        MergableDocument::from_document("line-art.pdf", line_art_doc),
+    ];
+    let mut merged_doc = merge_documents(mergable_docs).unwrap();
+
+    let summary = images::optimize_images(
+        &mut merged_doc,
+        &images::ImageOptions {
+            max_dpi: Some(150),
+            grayscale: true,
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(summary.images_found, 2);
+    assert_eq!(summary.images_rewritten, 2);
+    assert_eq!(summary.images_downsampled, 1);
+    assert!(summary.bytes_saved > 0);
+
+    let mut output = Vec::new();
+    save::write_document(&mut merged_doc, &mut output, save::OutputLayout::Classic).unwrap();
+    let reloaded = Document::load_mem(&output).unwrap();
+    let mut images: Vec<(i64, i64, Vec<u8>)> = reloaded
+        .objects
+        .values()
+        .filter_map(|object| object.as_stream().ok())
+        .filter(|stream| stream.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image".as_slice()))
+        .map(|stream| {
+            assert_eq!(stream.dict.get(b"ColorSpace").and_then(Object::as_name).unwrap(), b"DeviceGray");
+            (
+                stream.dict.get(b"Width").and_then(Object::as_i64).unwrap(),
+                stream.dict.get(b"BitsPerComponent").and_then(Object::as_i64).unwrap(),
+                stream.dict.get(b"Filter").and_then(Object::as_name).unwrap().to_vec(),
+            )
+        })
+        .collect();
+    images.sort();
+
+    assert_eq!(
+        images,
+        vec![(64, 1, b"FlateDecode".to_vec()), (300, 8, b"DCTDecode".to_vec())]
+    );
+
+    // Color-keyed images and images no page draws keep their exact pixels; masks do not count.
+    let photo = |x: u32, y: u32| [(x * 7 % 251) as u8, (y * 13 % 241) as u8, ((x + y) % 256) as u8];
+    let mut doc = create_sample_pdf("Keyed");
+    let keyed_id = add_image(&mut doc, "Im1", 600, 144, photo);
+    let mask_id = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject", "Subtype" => "Image", "Width" => 600, "Height" => 600,
+            "ColorSpace" => "DeviceGray", "BitsPerComponent" => 8,
+        },
+        vec![128; 600 * 600],
+    ));
+    let keyed = doc.get_object_mut(keyed_id).and_then(Object::as_stream_mut).unwrap();
+    keyed.dict.set("Mask", vec![0.into(), 10.into(), 0.into(), 10.into(), 0.into(), 10.into()]);
+    let mut unused = keyed.clone();
+    unused.dict.remove(b"Mask");
+    unused.dict.set("SMask", mask_id);
+    let unused_id = doc.add_object(unused);
+    let before = doc.clone();
+
+    let summary = images::optimize_images(
+        &mut doc,
+        &images::ImageOptions { max_dpi: Some(150), grayscale: true, ..Default::default() },
+    );
+
+    assert_eq!((summary.images_found, summary.images_rewritten), (2, 0));
+    for id in [keyed_id, unused_id, mask_id] {
+        assert_eq!(doc.get_object(id).unwrap(), before.get_object(id).unwrap());
+    }
+}
+
+#[test]
+fn test_compression_levels_and_decompression() {
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    let mut doc = create_sample_pdf("Compressed");
+    add_image(&mut doc, "Im1", 32, 72, |x, y| [x as u8, y as u8, 0]);
+    let mergable_docs = vec![
+        MergableDocument::from_document("image.pdf", doc),
+        MergableDocument::from_document("text.pdf", create_sample_pdf("Plain")),
+    ];
+    let mut merged_doc = merge_documents(mergable_docs).unwrap();
+    let image_id = merged_doc
+        .objects
+        .iter()
+        .find(|(_, object)| object.type_name().is_ok_and(|name| name == b"XObject"))
+        .map(|(id, _)| *id)
+        .unwrap();
+    let image_content = merged_doc.get_object(image_id).unwrap().as_stream().unwrap().content.clone();
+    // The text streams of the pages are large enough to shrink when compressed.
+    let filters = |doc: &Document| -> Vec<bool> {
+        doc.page_iter()
+            .filter_map(|page_id| doc.get_page_contents(page_id).first().copied())
+            .map(|content_id| doc.get_object(content_id).unwrap().as_stream().unwrap().is_compressed())
+            .collect()
+    };
+    assert!(filters(&merged_doc).iter().all(|compressed| !compressed));
+
+    compression::apply_compression(
+        &mut merged_doc,
+        &CompressionOptions {
+            streams: StreamCompression::Compress(compression::CompressionLevel::Max),
+            keep_compressed_images: true,
+        },
+    );
+    assert!(filters(&merged_doc).iter().all(|compressed| *compressed));
+    let image = merged_doc.get_object(image_id).unwrap().as_stream().unwrap();
+    assert_eq!(image.content, image_content);
+
+    // A stream whose filter is given indirectly is not taken for plain data.
+    let filter_id = merged_doc.add_object(Object::Name(b"FlateDecode".to_vec()));
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::none());
+    encoder.write_all(&b"BT /F1 12 Tf (Indirect) Tj ET\n".repeat(100)).unwrap();
+    let encoded = encoder.finish().unwrap();
+    let stream_id = merged_doc.add_object(Stream::new(dictionary! { "Filter" => filter_id }, encoded.clone()));
+    compression::apply_compression(
+        &mut merged_doc,
+        &CompressionOptions::compressed(compression::CompressionLevel::Max),
+    );
+    let stream = merged_doc.get_object(stream_id).unwrap().as_stream().unwrap();
+    assert_eq!(stream.content, encoded);
+    assert_eq!(stream.dict.get(b"Filter").unwrap(), &Object::Reference(filter_id));
+
+    compression::apply_compression(
+        &mut merged_doc,
+        &CompressionOptions {
+            streams: StreamCompression::Decompress,
+            keep_compressed_images: false,
+        },
+    );
+    assert!(filters(&merged_doc).iter().all(|compressed| !compressed));
+    let image = merged_doc.get_object(image_id).unwrap().as_stream().unwrap();
+    assert!(!image.is_compressed());
+    assert_eq!(image.content.len(), 32 * 32 * 3);
+    let content = String::from_utf8(merged_doc.get_page_content(merged_doc.page_iter().next().unwrap()).unwrap()).unwrap();
+    assert!(content.contains("Compressed"));
+}
+
+#[test]
+fn test_write_linearized() {
+    let mergable_docs = (0..3)
+        .map(|i| {
+            MergableDocument::from_document(
+                &format!("doc{}.pdf", i),
+                create_sample_pdf(&format!("Document {}", i)),
+            )
+        })
+        .collect();
+    let mut merged_doc = merge_documents(mergable_docs).unwrap();
+    let mut output = Vec::new();
+
+    save::write_document(&mut merged_doc, &mut output, save::OutputLayout::Linearized).unwrap();
+
+    let reloaded = Document::load_mem(&output).unwrap();
+    assert_eq!(reloaded.get_pages().len(), 3);
+    for (index, page_id) in reloaded.page_iter().enumerate() {
+        let content = String::from_utf8(reloaded.get_page_content(page_id).unwrap()).unwrap();
+        assert!(content.contains(&format!("Document {}", index)));
+    }
+
+    let linearization_start = output
+        .windows(16)
+        .position(|window| window == b"<</Linearized 1/")
+        .unwrap();
+    assert!(linearization_start < 1024);
+    let parameters = reloaded
+        .objects
+        .values()
+        .find_map(|object| object.as_dict().ok().filter(|dict| dict.has(b"Linearized")))
+        .unwrap();
+    let parameter = |key: &[u8]| parameters.get(key).and_then(Object::as_i64).unwrap() as usize;
+    let first_page_id = reloaded.page_iter().next().unwrap();
+    let object_at = |offset: usize| -> u32 {
+        let header = String::from_utf8_lossy(&output[offset..offset + 20]).to_string();
+        header.split(' ').next().unwrap().parse().unwrap()
+    };
+    let first_page_header = format!("\n{} 0 obj", first_page_id.0);
+
+    assert_eq!(parameter(b"L"), output.len());
+    assert_eq!(parameter(b"N"), 3);
+    assert_eq!(parameter(b"O"), first_page_id.0 as usize);
+    assert!(output[parameter(b"T")..].starts_with(b"\n0000000000 65535 f"));
+    assert!(output[..parameter(b"E")]
+        .windows(first_page_header.len())
+        .any(|window| window == first_page_header.as_bytes()));
+
+    let hint = parameters.get(b"H").and_then(Object::as_array).unwrap();
+    let hint_offset = hint[0].as_i64().unwrap() as usize;
+    let hint_length = hint[1].as_i64().unwrap() as usize;
+    let hint_stream = reloaded
+        .get_object((object_at(hint_offset), 0))
+        .and_then(Object::as_stream)
+        .unwrap();
+    assert!(output[hint_offset..hint_offset + hint_length].ends_with(b"endobj\n"));
+    let hints = hint_stream.decompressed_content().unwrap();
+    // The page offset hint table starts with the least number of objects per page and
+    // the offset of the first page, measured as if the hint stream was not there.
+    let first_page_offset = u32::from_be_bytes(hints[4..8].try_into().unwrap()) as usize;
+    assert_eq!(object_at(first_page_offset + hint_length), first_page_id.0);
+}
+
+#[test]
+fn test_metadata_inherits_from_input_and_matches_xmp() {
+    let mut source = create_sample_pdf("Source");
+    let info_id = source.add_object(dictionary! {
+        "Title" => Object::string_literal("Input title"),
+        "Author" => lopdf::text_string("Jürgen"),
+    });
+    source.trailer.set("Info", info_id);
+    let source = MergableDocument::from_document("source.pdf", source);
+    let mut metadata = metadata::DocumentMetadata {
+        title: Some("Bundle <2024>".to_string()),
+        ..Default::default()
+    };
+    metadata.inherit(source.get_metadata());
+    let mergable_docs = vec![
+        source,
+        MergableDocument::from_document("other.pdf", create_sample_pdf("Other")),
+    ];
+    let mut merged_doc = merge_documents(mergable_docs).unwrap();
+    // 2000-02-29 12:30:05 UTC
+    let created = metadata::Timestamp::from_system_time(
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(951_827_405),
+    );
+
+    metadata::apply_metadata(&mut merged_doc, &metadata, created, false);
+
+    let info = merged_doc
+        .get_dictionary(merged_doc.trailer.get(b"Info").and_then(Object::as_reference).unwrap())
+        .unwrap();
+    let text = |key: &[u8]| lopdf::decode_text_string(info.get(key).unwrap()).unwrap();
+    assert_eq!(text(b"Title"), "Bundle <2024>");
+    assert_eq!(text(b"Author"), "Jürgen");
+    assert!(text(b"Producer").starts_with("stapler "));
+    assert_eq!(text(b"CreationDate"), "D:20000229123005Z");
+
+    let xmp_id = merged_doc.catalog().unwrap().get(b"Metadata").and_then(Object::as_reference).unwrap();
+    let xmp = merged_doc.get_object(xmp_id).and_then(Object::as_stream).unwrap();
+    let xmp = String::from_utf8(xmp.content.clone()).unwrap();
+    assert!(xmp.contains("<rdf:li xml:lang=\"x-default\">Bundle &lt;2024&gt;</rdf:li>"));
+    assert!(xmp.contains("<rdf:li>Jürgen</rdf:li>"));
+    assert!(xmp.contains("<xmp:CreateDate>2000-02-29T12:30:05Z</xmp:CreateDate>"));
+    assert!(xmp.contains(&format!("<pdf:Producer>{}</pdf:Producer>", text(b"Producer"))));
+}
+
+#[test]
+fn test_output_version_follows_inputs_and_warns_on_downgrade() {
+    use save::OutputLayout;
+    use version::{select_output_version, set_output_version, PdfVersion};
+
+    let mut modern = create_sample_pdf("Modern");
+    modern.version = "1.4".to_string();
+    modern.catalog_mut().unwrap().set("Version", "1.7");
+    modern.add_object(Stream::new(dictionary! { "Type" => "ObjStm", "N" => 0, "First" => 0 }, Vec::new()));
+    let mut legacy = create_sample_pdf("Legacy");
+    legacy.version = "1.3".to_string();
+    // A cross-reference stream alone does not mean the file uses object streams.
+    legacy.reference_table.cross_reference_type = lopdf::xref::XrefType::CrossReferenceStream;
+    let modern = MergableDocument::from_document("modern.pdf", modern);
+    let legacy = MergableDocument::from_document("legacy.pdf", legacy);
+    assert_eq!(modern.get_features(), [version::PdfFeature::ObjectStreams]);
+    assert!(legacy.get_features().is_empty());
+    let inputs = [("modern.pdf", &modern), ("legacy.pdf", &legacy)];
+
+    let automatic = select_output_version(None, OutputLayout::ObjectStreams, &inputs);
+    assert_eq!(automatic.version.to_string(), "1.7");
+    assert_eq!(automatic.layout, OutputLayout::ObjectStreams);
+    assert!(automatic.warnings.is_empty());
+
+    let requested = "1.4".parse::<PdfVersion>().unwrap();
+    let downgraded = select_output_version(Some(requested), OutputLayout::ObjectStreams, &inputs);
+    assert_eq!(downgraded.version, requested);
+    assert_eq!(downgraded.layout, OutputLayout::Classic);
+    assert_eq!(downgraded.warnings.len(), 2);
+    assert!(downgraded.warnings[0].starts_with("modern.pdf uses object streams (PDF 1.5)"));
+
+    let mut merged_doc = merge_documents(vec![modern, legacy]).unwrap();
+    set_output_version(&mut merged_doc, automatic.version);
+    assert_eq!(merged_doc.version, "1.7");
+    set_output_version(&mut merged_doc, requested);
+    assert_eq!(merged_doc.version, "1.4");
+    assert!(merged_doc.catalog().unwrap().get(b"Version").is_err());
+
+    assert!("1.8".parse::<PdfVersion>().is_err());
+    assert!("2.0".parse::<PdfVersion>().is_ok());
+}
+
+#[test]
+fn test_pdfa_reports_violations_and_adds_output_intent() {
+    let mut scripted = create_sample_pdf("Scripted");
+    let script_id = scripted.add_object(dictionary! {
+        "S" => "JavaScript",
+        "JS" => Object::string_literal("app.alert('hi')"),
+    });
+    let link_id = scripted.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Link",
+        "Rect" => vec![0.into(), 0.into(), 10.into(), 10.into()],
+        "A" => script_id,
+    });
+    let page_id = *scripted.get_pages().values().next().unwrap();
+    scripted
+        .get_dictionary_mut(page_id)
+        .unwrap()
+        .set("Annots", vec![Object::Reference(link_id)]);
+    let mergable_docs = vec![
+        MergableDocument::from_document("plain.pdf", create_sample_pdf("Plain")),
+        MergableDocument::from_document("scripted.pdf", scripted),
+    ];
+    let merged_doc = merge_documents(mergable_docs).unwrap();
+
+    let violations = pdfa::find_pdfa_violations(&merged_doc);
+    let reasons = |page| {
+        violations
+            .iter()
+            .filter(|violation| violation.page == Some(page))
+            .map(|violation| violation.reason.as_str())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(reasons(1), ["font Courier is not embedded"]);
+    assert_eq!(
+        reasons(2),
+        ["an annotation uses a JavaScript action", "font Courier is not embedded"]
+    );
+
+    // Embedding the font makes the plain input conform.
+    let mut embedded = create_sample_pdf("Embedded");
+    let font_file = embedded.add_object(Stream::new(dictionary! {}, b"font program".to_vec()));
+    let descriptor = embedded.add_object(dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => "Courier",
+        "FontFile" => font_file,
+    });
+    for object in embedded.objects.values_mut() {
+        if let Ok(font) = object.as_dict_mut() {
+            if font.has_type(b"Font") {
+                font.set("FontDescriptor", descriptor);
+            }
+        }
+    }
+    let mergable_docs = vec![
+        MergableDocument::from_document("a.pdf", embedded.clone()),
+        MergableDocument::from_document("b.pdf", embedded),
+    ];
+    let mut merged_doc = merge_documents(mergable_docs).unwrap();
+    assert!(pdfa::find_pdfa_violations(&merged_doc).is_empty());
+
+    let created = metadata::Timestamp::from_system_time(std::time::UNIX_EPOCH);
+    metadata::apply_metadata(&mut merged_doc, &Default::default(), created, true);
+    pdfa::apply_pdfa(&mut merged_doc).unwrap();
+
+    let catalog = merged_doc.catalog().unwrap();
+    let intents = catalog.get(b"OutputIntents").and_then(Object::as_array).unwrap();
+    let intent = merged_doc.get_dictionary(intents[0].as_reference().unwrap()).unwrap();
+    assert!(matches!(intent.get(b"S").and_then(Object::as_name), Ok(b"GTS_PDFA1")));
+    let profile = merged_doc
+        .get_object(intent.get(b"DestOutputProfile").and_then(Object::as_reference).unwrap())
+        .and_then(Object::as_stream)
+        .unwrap();
+    assert_eq!(&profile.content[36..40], b"acsp");
+
+    let xmp_id = catalog.get(b"Metadata").and_then(Object::as_reference).unwrap();
+    let xmp = merged_doc.get_object(xmp_id).and_then(Object::as_stream).unwrap();
+    assert!(String::from_utf8_lossy(&xmp.content).contains("<pdfaid:part>2</pdfaid:part>"));
+    let id = merged_doc.trailer.get(b"ID").and_then(Object::as_array).unwrap();
+    assert_eq!(id.len(), 2);
+    assert_eq!(id[0], id[1]);
+}
+
+#[test]
+fn test_rotate_page_ranges_and_auto() {
+    let three_pages = merge_documents(
+        (1..=3)
+            .map(|i| MergableDocument::from_document(&format!("doc{}.pdf", i), create_sample_pdf("Page")))
+            .collect(),
+    )
+    .unwrap();
+    let mut ranged = MergableDocument::from_document("ranged.pdf", three_pages);
+    ranged
+        .rotate_pages(&"180,2-:270,3:auto".parse().unwrap())
+        .unwrap()
+        .select_pages(&"1,3".parse().unwrap())
+        .unwrap();
+
+    let mut landscape = create_sample_pdf("Landscape");
+    let page_id = *landscape.get_pages().values().next().unwrap();
+    landscape
+        .get_dictionary_mut(page_id)
+        .unwrap()
+        .set("MediaBox", vec![0.into(), 0.into(), 842.into(), 595.into()]);
+    let mut landscape = MergableDocument::from_document("landscape.pdf", landscape);
+    landscape.rotate_pages(&"auto".parse().unwrap()).unwrap();
+
+    let merged_doc = merge_documents(vec![ranged, landscape]).unwrap();
+
+    let rotations: Vec<i64> = merged_doc
+        .page_iter()
+        .map(|page_id| {
+            merged_doc
+                .get_dictionary(page_id)
+                .and_then(|page| page.get(b"Rotate"))
+                .and_then(Object::as_i64)
+                .unwrap_or(0)
+        })
+        .collect();
+    // Page 3 of the first input is portrait, so auto leaves it alone.
+    assert_eq!(rotations, vec![180, 0, 90]);
+
+    let invalid = MergableDocument::from_document("short.pdf", create_sample_pdf("Short"))
+        .rotate_pages(&"2:90".parse().unwrap())
+        .map(|_| ())
+        .unwrap_err();
+    assert!(format!("{:#}", invalid).contains("exceeds the document's 1 page(s)"));
+}
+
+#[test]
+fn test_normalize_page_sizes_to_letter() {
+    use page_size::{normalize_page_sizes, PageFit, PageSize, PageSizeOptions};
+
+    let mut receipt = create_sample_pdf("Receipt");
+    let link_id = receipt.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Link",
+        "Rect" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+    });
+    let page_id = *receipt.get_pages().values().next().unwrap();
+    let page = receipt.get_dictionary_mut(page_id).unwrap();
+    page.set("MediaBox", vec![0.into(), 0.into(), 300.into(), 800.into()]);
+    page.set("CropBox", vec![0.into(), 0.into(), 198.into(), 792.into()]);
+    page.set("Annots", vec![Object::Reference(link_id)]);
+    let mergable_docs = vec![
+        MergableDocument::from_document("receipt.pdf", receipt),
+        MergableDocument::from_document("a4.pdf", create_sample_pdf("A4")),
+    ];
+    let mut merged_doc = merge_documents(mergable_docs).unwrap();
+
+    let options = PageSizeOptions {
+        size: "letter".parse().unwrap(),
+        fit: PageFit::Fit,
+    };
+    normalize_page_sizes(&mut merged_doc, &options);
+
+    let pages = merged_doc.get_pages().into_values().collect::<Vec<_>>();
+    for page_id in &pages {
+        let page = merged_doc.get_dictionary(*page_id).unwrap();
+        let media_box = page
+            .get(b"MediaBox")
+            .and_then(Object::as_array)
+            .unwrap()
+            .iter()
+            .map(|value| value.as_float().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(media_box, [0.0, 0.0, 612.0, 792.0]);
+        assert!(page.get(b"CropBox").is_err());
+    }
+
+    // The receipt keeps its height and is centered horizontally: (612 - 198) / 2 = 207.
+    let contents = merged_doc.get_page_contents(pages[0]);
+    let prefix = merged_doc.get_object(contents[0]).and_then(Object::as_stream).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&prefix.content),
+        "q 1 0 0 1 207 0 cm 0 0 198 792 re W n\n"
+    );
+    let suffix = merged_doc.get_object(*contents.last().unwrap()).and_then(Object::as_stream).unwrap();
+    assert_eq!(suffix.content, b"\nQ");
+    let link = merged_doc
+        .get_page_annotations(pages[0])
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap();
+    let rect = link.get(b"Rect").and_then(Object::as_array).unwrap();
+    assert_eq!(rect[0].as_float().unwrap(), 207.0);
+
+    let a4 = "210x297mm".parse::<PageSize>().unwrap();
+    assert!((a4.width - PageSize::A4.width).abs() < 0.01);
+    assert!((a4.height - PageSize::A4.height).abs() < 0.01);
+    assert!("a4 paper".parse::<PageSize>().is_err());
+}
+
+#[test]
+fn test_booklet_imposition_orders_pages_for_folding() {
+    use imposition::{impose_pages, Imposition};
+
+    let mergable_docs = (0..6)
+        .map(|i| MergableDocument::from_document(&format!("doc{}.pdf", i), create_sample_pdf(&format!("Page {}", i))))
+        .collect::<Vec<_>>();
+    let mut merged_doc = merge_documents(mergable_docs).unwrap();
+    let page_ids = merged_doc.get_pages().into_values().collect::<Vec<_>>();
+
+    impose_pages(&mut merged_doc, &"booklet".parse().unwrap()).unwrap();
+
+    // Six pages are padded to eight, printed on two sheets of two sides each.
+    let sheets = merged_doc.get_pages().into_values().collect::<Vec<_>>();
+    assert_eq!(sheets.len(), 4);
+    let placed = |sheet_id: ObjectId| {
+        let resources = merged_doc.get_dictionary(sheet_id).and_then(|sheet| sheet.get(b"Resources")).unwrap();
+        let xobjects = resources.as_dict().and_then(|resources| resources.get(b"XObject")).and_then(Object::as_dict).unwrap();
+        xobjects.iter().map(|(name, _)| String::from_utf8_lossy(name).to_string()).collect::<Vec<_>>()
+    };
+    // The outer front shows the blank last page and page 1, the outer back page 2 and a blank.
+    assert_eq!(placed(sheets[0]), ["P1"]);
+    assert_eq!(placed(sheets[1]), ["P0"]);
+    assert_eq!(placed(sheets[3]), ["P0", "P1"]);
+    let sheet = merged_doc.get_dictionary(sheets[0]).unwrap();
+    let media_box = sheet.get(b"MediaBox").and_then(Object::as_array).unwrap();
+    assert_eq!(media_box[2].as_float().unwrap(), 842.0);
+    assert_eq!(media_box[3].as_float().unwrap(), 595.0);
+    assert!(page_ids.iter().all(|page_id| merged_doc.get_object(*page_id).is_err()));
+
+    // Bookmarks now open the sheet showing their page: pages 3 and 4 share the inner front.
+    let destinations = merged_doc
+        .objects
+        .values()
+        .filter_map(|object| object.as_dict().ok())
+        .filter(|dict| matches!(dict.get(b"S").and_then(Object::as_name), Ok(b"GoTo")))
+        .filter_map(|action| action.get(b"D").and_then(Object::as_array).ok())
+        .map(|destination| destination[0].as_reference().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(destinations.len(), 6);
+    assert!(destinations.iter().all(|id| sheets.contains(id)));
+    assert_eq!(destinations.iter().filter(|id| **id == sheets[2]).count(), 2);
+
+    assert_eq!("3x2".parse::<Imposition>().unwrap(), Imposition::Grid { columns: 3, rows: 2 });
+    assert_eq!("4-up".parse::<Imposition>().unwrap(), Imposition::Grid { columns: 2, rows: 2 });
+    assert!("0x2".parse::<Imposition>().is_err());
+}
+
+#[test]
+fn test_imposition_moves_tagged_content_to_the_sheet() {
+    use imposition::{impose_pages, Imposition};
+
+    let mergable_docs = (0..2)
+        .map(|i| MergableDocument::from_document(&format!("doc{}.pdf", i), create_sample_pdf(&format!("Page {}", i))))
+        .collect::<Vec<_>>();
+    let mut merged_doc = merge_documents(mergable_docs).unwrap();
+    let page_ids = merged_doc.get_pages().into_values().collect::<Vec<_>>();
+    // The second page draws from two content streams.
+    let extra_id = merged_doc.add_object(Stream::new(dictionary! {}, b"q Q\n".repeat(200)));
+    let first_content = merged_doc.get_page_contents(page_ids[1])[0];
+    merged_doc
+        .get_dictionary_mut(page_ids[1])
+        .unwrap()
+        .set("Contents", vec![first_content.into(), extra_id.into()]);
+
+    let root_id = merged_doc.new_object_id();
+    let first_id = merged_doc.add_object(dictionary! {
+        "Type" => "StructElem", "S" => "P", "P" => root_id, "Pg" => page_ids[0], "K" => 0,
+    });
+    let second_id = merged_doc.add_object(dictionary! {
+        "Type" => "StructElem", "S" => "P", "P" => root_id, "Pg" => page_ids[1],
+        "K" => vec![dictionary! { "Type" => "MCR", "MCID" => 0 }.into()],
+    });
+    merged_doc.objects.insert(
+        root_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "StructTreeRoot",
+            "K" => vec![first_id.into(), second_id.into()],
+            "ParentTree" => dictionary! {
+                "Nums" => vec![0.into(), vec![first_id.into()].into(), 1.into(), vec![second_id.into()].into()],
+            },
+        }),
+    );
+    for (index, page_id) in page_ids.iter().enumerate() {
+        merged_doc.get_dictionary_mut(*page_id).unwrap().set("StructParents", index as i64);
+    }
+    merged_doc.catalog_mut().unwrap().set("StructTreeRoot", root_id);
+
+    impose_pages(&mut merged_doc, &Imposition::Grid { columns: 2, rows: 1 }).unwrap();
+    compression::apply_compression(
+        &mut merged_doc,
+        &CompressionOptions::compressed(compression::CompressionLevel::Default),
+    );
+
+    let sheet_id = merged_doc.page_iter().next().unwrap();
+    let sheet = merged_doc.get_dictionary(sheet_id).unwrap();
+    let xobjects = sheet.get(b"Resources").and_then(Object::as_dict).and_then(|resources| resources.get(b"XObject")).and_then(Object::as_dict).unwrap();
+    let forms = [b"P0".as_slice(), b"P1"].map(|name| xobjects.get(name).and_then(Object::as_reference).unwrap());
+    let struct_parents = forms.map(|form_id| {
+        merged_doc.get_object(form_id).and_then(Object::as_stream).unwrap().dict.get(b"StructParents").and_then(Object::as_i64).unwrap()
+    });
+    assert_eq!(struct_parents, [0, 1]);
+    // The joined content streams of the second page are compressed like any other stream.
+    assert!(merged_doc.get_object(forms[1]).and_then(Object::as_stream).unwrap().is_compressed());
+
+    for (element_id, form_id) in [first_id, second_id].into_iter().zip(forms) {
+        let element = merged_doc.get_dictionary(element_id).unwrap();
+        assert_eq!(element.get(b"Pg").and_then(Object::as_reference).unwrap(), sheet_id);
+        let content_item = match element.get(b"K").unwrap() {
+            Object::Array(kids) => kids[0].as_dict().unwrap(),
+            kid => kid.as_dict().unwrap(),
+        };
+        assert_eq!(content_item.get(b"MCID").and_then(Object::as_i64).unwrap(), 0);
+        assert_eq!(content_item.get(b"Stm").and_then(Object::as_reference).unwrap(), form_id);
+    }
+}
+
+#[test]
+fn test_crop_inputs_and_add_binding_margins() {
+    use crop::{add_margins, PageCrop, PageMargins};
+
+    let float_array = |document: &Document, page_id: ObjectId, key: &[u8]| {
+        document
+            .get_dictionary(page_id)
+            .and_then(|page| page.get(key))
+            .and_then(Object::as_array)
+            .unwrap()
+            .iter()
+            .map(|value| value.as_float().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let mut scan = MergableDocument::from_document("scan.pdf", create_sample_pdf("Scan"));
+    scan.crop_pages(&"10%,5%".parse().unwrap()).unwrap();
+    let mut letter = MergableDocument::from_document("letter.pdf", create_sample_pdf("Letter"));
+    letter.crop_pages(&"1,2,3,4in".parse().unwrap()).unwrap();
+    letter.rotate_pages(&options::Rotation::Clockwise90.into()).unwrap();
+    let mut merged_doc = merge_documents(vec![scan, letter]).unwrap();
+    let pages = merged_doc.get_pages().into_values().collect::<Vec<_>>();
+
+    // 10% of 842 off the top and bottom, 5% of 595 off the sides.
+    let crop_box = float_array(&merged_doc, pages[0], b"CropBox");
+    assert!((crop_box[0] - 29.75).abs() < 0.01 && (crop_box[1] - 84.2).abs() < 0.01);
+    assert!((crop_box[2] - 565.25).abs() < 0.01 && (crop_box[3] - 757.8).abs() < 0.01);
+    assert_eq!(float_array(&merged_doc, pages[1], b"CropBox"), [72.0, 144.0, 216.0, 288.0]);
+
+    let margins = PageMargins { margin: 10.0, gutter: 20.0 };
+    add_margins(&mut merged_doc, &margins);
+
+    // The gutter is on the left of the first page. The second page is turned clockwise,
+    // so its displayed right edge is the top of the unrotated page.
+    let media_box = float_array(&merged_doc, pages[0], b"MediaBox");
+    assert!((media_box[0] - (29.75 - 30.0)).abs() < 0.01 && (media_box[2] - 575.25).abs() < 0.01);
+    assert_eq!(float_array(&merged_doc, pages[1], b"MediaBox"), [62.0, 134.0, 226.0, 318.0]);
+    assert!(merged_doc.get_dictionary(pages[1]).unwrap().get(b"CropBox").is_err());
+    let contents = merged_doc.get_page_contents(pages[1]);
+    let clip = merged_doc.get_object(contents[0]).and_then(Object::as_stream).unwrap();
+    assert_eq!(String::from_utf8_lossy(&clip.content), "q 72 144 144 144 re W n\n");
+
+    assert!("50%,0%,50%,0%".parse::<PageCrop>().is_err());
+    assert!("10,10,5,20".parse::<PageCrop>().is_err());
+    assert_eq!("2%".parse::<PageCrop>().unwrap(), PageCrop::Margins([2.0; 4]));
+}
+
+#[test]
+fn test_remove_blank_pages_reports_input_page_numbers() {
+    use blank::BlankPageOptions;
+
+    let scan = |images: bool| {
+        let pages = (0..6).map(|i| MergableDocument::from_document(&format!("p{}.pdf", i), create_sample_pdf("Text")));
+        let mut doc = merge_documents(pages.collect()).unwrap();
+        let page_ids = doc.get_pages().into_values().collect::<Vec<_>>();
+        let mut add_image = |page_id: ObjectId, gray: u8| {
+            let pixels = (0..100 * 100).map(|index| if index == 0 { 0 } else { gray }).collect();
+            let image = Stream::new(
+                dictionary! {
+                    "Type" => "XObject",
+                    "Subtype" => "Image",
+                    "Width" => 100,
+                    "Height" => 100,
+                    "ColorSpace" => "DeviceGray",
+                    "BitsPerComponent" => 8,
+                },
+                pixels,
+            );
+            let image_id = doc.add_object(image);
+            let resources_id = doc.get_dictionary(page_id).and_then(|page| page.get(b"Resources")).and_then(Object::as_reference).unwrap();
+            doc.get_dictionary_mut(resources_id).unwrap().set("XObject", dictionary! { "Im0" => image_id });
+        };
+        add_image(page_ids[4], 250);
+        add_image(page_ids[5], 40);
+        let contents: [&[u8]; 6] = [
+            b"BT /F1 12 Tf (Text) Tj ET",
+            b"",
+            b"BT /F1 12 Tf ( \t) Tj ET",
+            b"q 1 g 0 0 595 842 re f Q",
+            b"q 595 0 0 842 0 0 cm /Im0 Do Q",
+            b"q 595 0 0 842 0 0 cm /Im0 Do Q",
+        ];
+        for (page_id, content) in page_ids.iter().zip(contents) {
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content.to_vec()));
+            doc.get_dictionary_mut(*page_id).unwrap().set("Contents", content_id);
+        }
+
+        let mut scan = MergableDocument::from_document("scan.pdf", doc);
+        scan.select_pages(&"1,2,4-6".parse().unwrap()).unwrap();
+        scan.remove_blank_pages(&BlankPageOptions { images });
+        (scan.get_removed_blank_pages().to_vec(), scan.get_page_count())
+    };
+
+    // Page 3 was not selected, the gray page 5 only counts as blank when images are checked.
+    assert_eq!(scan(false), (vec![2, 4], 3));
+    assert_eq!(scan(true), (vec![2, 4, 5], 2));
+
+    let mut blank = create_sample_pdf("Blank");
+    let page_id = blank.page_iter().next().unwrap();
+    let empty = blank.add_object(Stream::new(dictionary! {}, Vec::new()));
+    blank.get_dictionary_mut(page_id).unwrap().set("Contents", empty);
+    let mut blank = MergableDocument::from_document("blank.pdf", blank);
+    blank.remove_blank_pages(&BlankPageOptions::default());
+    assert!(blank.is_empty());
+    assert_eq!(blank.get_removed_blank_pages(), [1]);
+}
+
+#[test]
+fn test_load_images_as_pages() {
+    use image::{codecs::jpeg::JpegEncoder, ImageEncoder, ImageFormat, RgbImage, RgbaImage};
+    use image_pages::ImagePageOptions;
+    use loader::{fs::FileSystemMergingSource, DocumentLoader};
+    use page_size::PageSize;
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    let directory = std::env::temp_dir().join(format!("stapler-image-inputs-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    // A two-page scan: a landscape gray page and a portrait color page.
+    let mut tiff = std::io::Cursor::new(Vec::new());
+    let mut encoder = TiffEncoder::new(&mut tiff).unwrap();
+    encoder.write_image::<colortype::Gray8>(200, 100, &[255; 200 * 100]).unwrap();
+    encoder.write_image::<colortype::RGB8>(50, 100, &[128; 50 * 100 * 3]).unwrap();
+    std::fs::write(directory.join("scan.tif"), tiff.into_inner()).unwrap();
+    RgbaImage::from_fn(72, 144, |x, _| image::Rgba([0, 0, 255, if x < 36 { 255 } else { 0 }]))
+        .save_with_format(directory.join("logo.png"), ImageFormat::Png)
+        .unwrap();
+    RgbImage::from_pixel(144, 72, image::Rgb([200, 10, 10]))
+        .save_with_format(directory.join("photo"), ImageFormat::Jpeg)
+        .unwrap();
+    // The same photo taken with the camera on its side, EXIF orientation 6.
+    let mut turned = Vec::new();
+    let mut encoder = JpegEncoder::new(&mut turned);
+    let exif = [b"MM\0*".as_slice(), &[0, 0, 0, 8, 0, 1, 0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]].concat();
+    encoder.set_exif_metadata(exif).unwrap();
+    encoder
+        .write_image(&[200, 10, 10].repeat(144 * 72), 144, 72, image::ExtendedColorType::Rgb8)
+        .unwrap();
+    std::fs::write(directory.join("turned.jpg"), turned).unwrap();
+
+    let load = |name: &str, image: ImagePageOptions| {
+        let source = FileSystemMergingSource {
+            input_file: directory.join(name).to_string_lossy().to_string(),
+            image,
+            ..Default::default()
+        };
+        source.load().unwrap()
+    };
+    let media_boxes = |document: &MergableDocument| {
+        document
+            .get_pages()
+            .values()
+            .map(|page| {
+                let media_box = page.as_dict().unwrap().get(b"MediaBox").unwrap().as_array().unwrap();
+                [media_box[2].as_float().unwrap(), media_box[3].as_float().unwrap()]
+            })
+            .collect::<Vec<_>>()
+    };
+    let image_filters = |document: &MergableDocument| {
+        let mut filters = document
+            .get_objects()
+            .values()
+            .filter_map(|object| object.as_stream().ok())
+            .filter(|stream| matches!(stream.dict.get(b"Subtype").and_then(Object::as_name), Ok(b"Image")))
+            .map(|stream| String::from_utf8_lossy(stream.dict.get(b"Filter").unwrap().as_name().unwrap()).to_string())
+            .collect::<Vec<_>>();
+        filters.sort();
+        filters
+    };
+
+    let at_72_dpi = ImagePageOptions { dpi: 72, page_size: None };
+    let scan = load("scan.tif", at_72_dpi);
+    assert_eq!(media_boxes(&scan), [[200.0, 100.0], [50.0, 100.0]]);
+    assert_eq!(media_boxes(&load("scan.tif", ImagePageOptions::default())), [[48.0, 24.0], [12.0, 24.0]]);
+    let a4 = ImagePageOptions { dpi: 72, page_size: Some(PageSize::A4) };
+    let fitted = media_boxes(&load("scan.tif", a4));
+    assert_eq!(fitted, [[841.89, 595.276], [595.276, 841.89]]);
+
+    // The half transparent PNG gets a soft mask, the JPEG is embedded without re-encoding.
+    let logo = load("logo.png", at_72_dpi);
+    assert_eq!(media_boxes(&logo), [[72.0, 144.0]]);
+    assert_eq!(image_filters(&logo), ["FlateDecode", "FlateDecode"]);
+    let photo = load("photo", at_72_dpi);
+    assert_eq!(image_filters(&photo), ["DCTDecode"]);
+    let turned = load("turned.jpg", at_72_dpi);
+    assert_eq!(media_boxes(&turned), [[72.0, 144.0]]);
+    assert_eq!(image_filters(&turned), ["DCTDecode"]);
+    let contents = turned
+        .get_objects()
+        .values()
+        .filter_map(|object| object.as_stream().ok())
+        .filter(|stream| stream.dict.get(b"Subtype").is_err())
+        .map(|stream| String::from_utf8_lossy(&stream.content).to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(contents, ["q 0 -144 72 0 0 144 cm /Im0 Do Q"]);
+
+    let merged = merge_documents(vec![scan, logo, photo]).unwrap();
+    assert_eq!(merged.get_pages().len(), 4);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_load_text_as_monospaced_pages() {
+    use loader::{fs::FileSystemMergingSource, DocumentLoader};
+    use page_size::PageSize;
+    use text_pages::{text_document, TextPageOptions};
+
+    let shown_lines = |document: &Document| {
+        document
+            .page_iter()
+            .map(|page_id| {
+                let content = Content::decode(&document.get_page_content(page_id).unwrap()).unwrap();
+                content
+                    .operations
+                    .iter()
+                    .filter(|operation| operation.operator == "Tj")
+                    .map(|operation| operation.operands[0].as_str().unwrap().iter().map(|byte| *byte as char).collect::<String>())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+    // 10pt Courier with 1in margins leaves 24 columns and 7 lines on this page.
+    let small = TextPageOptions {
+        font_size: 10.0,
+        margin: crop::Length(72.0),
+        page_size: PageSize { width: 288.0, height: 230.0 },
+        wrap: true,
+    };
+    let text = "a\tb\nthe quick brown fox jumps over the lazy dog\n\u{c}2\n3\n4\n5\n6\n7\n8\n\u{c}";
+    let pages = shown_lines(&text_document(text, &small).unwrap());
+    assert_eq!(
+        pages,
+        [
+            vec!["a       b", "the quick brown fox", "jumps over the lazy dog"],
+            vec!["2", "3", "4", "5", "6", "7", "8"],
+        ]
+    );
+    let cut = shown_lines(&text_document(text, &TextPageOptions { wrap: false, ..small }).unwrap());
+    assert_eq!(cut[0][1], "the quick brown fox jump");
+    let narrow = TextPageOptions { margin: crop::Length(150.0), ..small };
+    assert!(text_document(text, &narrow).is_err());
+
+    let file = std::env::temp_dir().join(format!("stapler-notes-{}.TXT", std::process::id()));
+    std::fs::write(&file, "%PDF- is quoted, not a header\nCafé (draft\n").unwrap();
+    let source = FileSystemMergingSource::new(&file.to_string_lossy());
+    let document = source.load().unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(document.get_page_count(), 1);
+    let merged = merge_documents(vec![document, MergableDocument::from_document("b.pdf", create_sample_pdf("B"))]).unwrap();
+    assert_eq!(merged.get_pages().len(), 2);
+    let notes = text_document("Café (draft", &TextPageOptions::default()).unwrap();
+    assert_eq!(shown_lines(&notes), [vec!["Caf\u{e9} (draft"]]);
+}
+
+/// Embeds `files` as (name, contents) through a name tree with one kid.
+fn attach_files(doc: &mut Document, files: &[(&str, &[u8])]) {
+    let entries = files
+        .iter()
+        .flat_map(|(name, data)| {
+            let mut stream = Stream::new(dictionary! { "Type" => "EmbeddedFile", "Subtype" => "text/plain" }, data.to_vec());
+            stream.compress().unwrap();
+            let stream_id = doc.add_object(stream);
+            let file_id = doc.add_object(dictionary! {
+                "Type" => "Filespec",
+                "F" => Object::string_literal(*name),
+                "UF" => lopdf::text_string(name),
+                "EF" => dictionary! { "F" => stream_id },
+            });
+            [Object::string_literal(*name), Object::Reference(file_id)]
+        })
+        .collect::<Vec<_>>();
+    let kid_id = doc.add_object(dictionary! { "Names" => entries });
+    let names_id = doc.add_object(dictionary! {
+        "EmbeddedFiles" => dictionary! { "Kids" => vec![Object::Reference(kid_id)] },
+    });
+    doc.catalog_mut().unwrap().set("Names", names_id);
+}
+
+#[test]
+fn test_merge_keeps_attachments_of_every_input() {
+    use attachments::list_attachments;
+
+    let mut first = create_sample_pdf("A");
+    attach_files(&mut first, &[("notes.txt", b"first notes")]);
+    let mut second = create_sample_pdf("B");
+    attach_files(&mut second, &[("notes.txt", b"second notes"), ("data.csv", b"a,b\n1,2\n")]);
+    let third = create_sample_pdf("C");
+    let documents = [first, second, third]
+        .into_iter()
+        .enumerate()
+        .map(|(index, doc)| MergableDocument::from_document(&format!("{}.pdf", index), doc))
+        .collect();
+
+    let mut merged = merge_documents(documents).unwrap();
+    let mut saved = Vec::new();
+    merged.save_to(&mut saved).unwrap();
+    let reloaded = Document::load_mem(&saved).unwrap();
+    let attachments = list_attachments(&reloaded)
+        .unwrap()
+        .into_iter()
+        .map(|attachment| (attachment.name, attachment.file_name, attachment.data))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        attachments,
+        [
+            ("data.csv".to_string(), "data.csv".to_string(), b"a,b\n1,2\n".to_vec()),
+            ("notes.txt".to_string(), "notes.txt".to_string(), b"first notes".to_vec()),
+            ("notes.txt (2)".to_string(), "notes.txt".to_string(), b"second notes".to_vec()),
+        ]
+    );
+
+    // An attachment that cannot be read does not hide the others from the PDF/A check.
+    let (_, file) = attachments::embedded_files(&reloaded).into_iter().next().unwrap();
+    let mut reloaded = reloaded;
+    reloaded.get_dictionary_mut(file.as_reference().unwrap()).unwrap().remove(b"EF");
+    let violations = pdfa::find_pdfa_violations(&reloaded)
+        .into_iter()
+        .filter(|violation| violation.page.is_none())
+        .map(|violation| violation.reason)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        violations,
+        [
+            "the attachment data.csv cannot be read",
+            "the attachment notes.txt is not a PDF file",
+            "the attachment notes.txt is not a PDF file",
+        ]
+    );
+}
+
+#[test]
+fn test_attach_original_input_files() {
+    use attachments::list_attachments;
+    use md5::{Digest, Md5};
+
+    let directory = std::env::temp_dir().join(format!("stapler-attach-inputs-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let mut documents = Vec::new();
+    for (index, title) in ["A", "B"].into_iter().enumerate() {
+        let mut doc = create_sample_pdf(title);
+        if index == 1 {
+            attach_files(&mut doc, &[("notes.txt", b"notes")]);
+        }
+        let path = directory.join(format!("{}.pdf", title));
+        doc.save(&path).unwrap();
+        let mut document = MergableDocument::from_document(&format!("{}.pdf", title), Document::load(&path).unwrap());
+        document.attach_original_file(&path).unwrap();
+        documents.push(document);
+    }
+
+    let mut merged = merge_documents(documents).unwrap();
+    let mut saved = Vec::new();
+    merged.save_to(&mut saved).unwrap();
+    let reloaded = Document::load_mem(&saved).unwrap();
+    let attachments = list_attachments(&reloaded).unwrap();
+    let names = attachments.iter().map(|attachment| attachment.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["A.pdf", "B.pdf", "notes.txt"]);
+    for attachment in &attachments[..2] {
+        let original = std::fs::read(directory.join(&attachment.file_name)).unwrap();
+        assert_eq!(attachment.data, original);
+        assert_eq!(attachment.mime_type.as_deref(), Some("application/pdf"));
+        assert!(attachment.modified.as_deref().is_some_and(|date| date.starts_with("D:")));
+    }
+
+    let (_, file) = &attachments::embedded_files(&reloaded)[0];
+    let file = reloaded.dereference(file).unwrap().1.as_dict().unwrap();
+    let stream_id = file.get(b"EF").unwrap().as_dict().unwrap().get(b"F").unwrap().as_reference().unwrap();
+    let params = reloaded.get_object(stream_id).unwrap().as_stream().unwrap().dict.get(b"Params").unwrap().as_dict().unwrap();
+    let checksum = params.get(b"CheckSum").unwrap().as_str().unwrap();
+    assert_eq!(checksum, Md5::digest(&attachments[0].data).as_slice());
+    assert_eq!(params.get(b"Size").unwrap().as_i64().unwrap(), attachments[0].size as i64);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
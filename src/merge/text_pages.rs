@@ -0,0 +1,230 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result};
+use lopdf::{
+    content::{Content, Operation},
+    dictionary,
+    xref::XrefType,
+    Document, Object, Stream, StringFormat,
+};
+use serde::Deserialize;
+
+use super::crop::Length;
+use super::loader::{DocumentLoader, MergableDocument};
+use super::page_size::PageSize;
+
+/// Advance of every Courier glyph, as a share of the font size.
+const COURIER_ADVANCE: f32 = 0.6;
+/// Distance between baselines, as a share of the font size.
+const LINE_SPACING: f32 = 1.2;
+const TAB_WIDTH: usize = 8;
+
+fn default_font_size() -> f32 {
+    10.0
+}
+
+fn default_margin() -> Length {
+    Length(36.0)
+}
+
+fn default_page_size() -> PageSize {
+    PageSize::A4
+}
+
+fn default_wrap() -> bool {
+    true
+}
+
+/// How plain-text inputs are laid out on pages.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextPageOptions {
+    /// Size of the monospaced font in points.
+    #[serde(default = "default_font_size")]
+    pub font_size: f32,
+    /// White space kept free on all four sides.
+    #[serde(default = "default_margin")]
+    pub margin: Length,
+    #[serde(default = "default_page_size")]
+    pub page_size: PageSize,
+    /// Continue lines longer than the page is wide on the next line, instead of cutting
+    /// them off.
+    #[serde(default = "default_wrap")]
+    pub wrap: bool,
+}
+
+impl Default for TextPageOptions {
+    fn default() -> Self {
+        TextPageOptions {
+            font_size: default_font_size(),
+            margin: default_margin(),
+            page_size: default_page_size(),
+            wrap: default_wrap(),
+        }
+    }
+}
+
+/// Reads text as UTF-8, falling back to Latin-1 for files that are not.
+fn decode_text(data: Vec<u8>) -> String {
+    let text = String::from_utf8(data).unwrap_or_else(|error| error.into_bytes().iter().map(|byte| *byte as char).collect());
+    text.strip_prefix('\u{feff}').map(str::to_string).unwrap_or(text)
+}
+
+/// The WinAnsiEncoding code of `c`, `?` for characters the encoding lacks.
+fn win_ansi(c: char) -> u8 {
+    match c {
+        ' '..='~' | '\u{a0}'..='\u{ff}' => c as u8,
+        '€' => 0x80,
+        '‚' => 0x82,
+        '„' => 0x84,
+        '…' => 0x85,
+        '‘' => 0x91,
+        '’' => 0x92,
+        '“' => 0x93,
+        '”' => 0x94,
+        '•' => 0x95,
+        '–' => 0x96,
+        '—' => 0x97,
+        '™' => 0x99,
+        _ => b'?',
+    }
+}
+
+/// Splits one line of text into the lines shown, tabs expanded. With `wrap`, long lines
+/// break after the last space that fits, or at `columns` if there is none.
+fn layout_line(line: &str, columns: usize, wrap: bool) -> Vec<Vec<u8>> {
+    let mut expanded = Vec::new();
+    for c in line.chars() {
+        match c {
+            '\t' => expanded.resize((expanded.len() / TAB_WIDTH + 1) * TAB_WIDTH, b' '),
+            c if c.is_control() => {}
+            c => expanded.push(win_ansi(c)),
+        }
+    }
+    if !wrap {
+        expanded.truncate(columns);
+        return vec![expanded];
+    }
+    let mut lines = Vec::new();
+    let mut rest = expanded.as_slice();
+    while rest.len() > columns {
+        let end = rest[..=columns]
+            .iter()
+            .rposition(|byte| *byte == b' ')
+            .filter(|space| *space > 0)
+            .unwrap_or(columns);
+        lines.push(rest[..end].to_vec());
+        rest = &rest[end..];
+        if rest.first() == Some(&b' ') {
+            rest = &rest[1..];
+        }
+    }
+    lines.push(rest.to_vec());
+    lines
+}
+
+/// Lays out `text` in pages of lines. Form feeds start a new page.
+fn layout_pages(text: &str, columns: usize, lines_per_page: usize, wrap: bool) -> Vec<Vec<Vec<u8>>> {
+    let text = text.replace("\r\n", "\n");
+    let mut sections = text.split('\u{c}').collect::<Vec<_>>();
+    // A form feed ending the file does not start another page.
+    if sections.len() > 1 && sections.last().is_some_and(|section| section.trim_end_matches('\n').is_empty()) {
+        sections.pop();
+    }
+    let mut pages = Vec::new();
+    for section in sections {
+        let section = section.strip_suffix('\n').unwrap_or(section);
+        let lines = section
+            .split(['\n', '\r'])
+            .flat_map(|line| layout_line(line, columns, wrap))
+            .collect::<Vec<_>>();
+        pages.extend(lines.chunks(lines_per_page).map(<[Vec<u8>]>::to_vec));
+    }
+    pages
+}
+
+/// Builds a document showing `text` in Courier, a page after the other.
+pub fn text_document(text: &str, options: &TextPageOptions) -> Result<Document> {
+    let TextPageOptions { font_size, margin: Length(margin), page_size, wrap } = *options;
+    anyhow::ensure!(font_size > 0.0, "The font size must be greater than 0");
+    let leading = (font_size * LINE_SPACING * 100.0).round() / 100.0;
+    let (text_width, text_height) = (page_size.width - 2.0 * margin, page_size.height - 2.0 * margin);
+    let columns = (text_width / (font_size * COURIER_ADVANCE)).floor();
+    let lines_per_page = ((text_height - font_size) / leading).floor() + 1.0;
+    anyhow::ensure!(
+        columns >= 1.0 && lines_per_page >= 1.0,
+        "The margins leave no room for text on the page"
+    );
+
+    let mut document = Document::with_version("1.4");
+    document.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+    let pages_id = document.new_object_id();
+    let font_id = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Courier",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let resources_id = document.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => font_id },
+    });
+    let mut kids = Vec::new();
+    for lines in layout_pages(text, columns as usize, lines_per_page as usize, wrap) {
+        let mut operations = vec![
+            Operation::new("BT", vec![]),
+            Operation::new("Tf", vec!["F1".into(), font_size.into()]),
+            Operation::new("TL", vec![leading.into()]),
+            Operation::new("Td", vec![margin.into(), (page_size.height - margin - font_size).into()]),
+        ];
+        for line in lines {
+            operations.push(Operation::new("Tj", vec![Object::String(line, StringFormat::Literal)]));
+            operations.push(Operation::new("T*", vec![]));
+        }
+        operations.push(Operation::new("ET", vec![]));
+        let content = Content { operations }.encode()?;
+        let content_id = document.add_object(Stream::new(dictionary! {}, content));
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), page_size.width.into(), page_size.height.into()],
+            "Contents" => content_id,
+            "Resources" => resources_id,
+        });
+        kids.push(Object::Reference(page_id));
+    }
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as u32,
+            "Kids" => kids,
+        }),
+    );
+    let catalog_id = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    document.trailer.set("Root", catalog_id);
+    Ok(document)
+}
+
+/// Loads a plain-text file as monospaced pages.
+#[derive(Debug, Clone, Default)]
+pub struct TextMergingSource {
+    pub input_file: String,
+    pub options: TextPageOptions,
+}
+
+impl DocumentLoader for TextMergingSource {
+    fn load(&self) -> Result<MergableDocument> {
+        let text = fs::read(&self.input_file)
+            .map(decode_text)
+            .with_context(|| format!("Failed to load {}", self.input_file))?;
+        let pdf = text_document(&text, &self.options)
+            .with_context(|| format!("Failed to lay out {}", self.input_file))?;
+        let original_filename = Path::new(&self.input_file)
+            .file_name()
+            .map_or(self.input_file.clone(), |name| name.to_string_lossy().to_string());
+        Ok(MergableDocument::from_document(&original_filename, pdf))
+    }
+}
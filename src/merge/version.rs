@@ -0,0 +1,202 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::Result;
+use lopdf::{Document, Object};
+use serde::Deserialize;
+
+use super::loader::MergableDocument;
+use super::save::OutputLayout;
+
+/// A PDF version as written in the file header, `1.0` up to `2.0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "VersionValue")]
+pub struct PdfVersion {
+    major: u8,
+    minor: u8,
+}
+
+impl PdfVersion {
+    pub const V1_4: PdfVersion = PdfVersion { major: 1, minor: 4 };
+    pub const V1_5: PdfVersion = PdfVersion { major: 1, minor: 5 };
+    pub const V1_6: PdfVersion = PdfVersion { major: 1, minor: 6 };
+    pub const V1_7: PdfVersion = PdfVersion { major: 1, minor: 7 };
+    pub const V2_0: PdfVersion = PdfVersion { major: 2, minor: 0 };
+
+    /// The version a document declares: the header, raised by the catalog's `/Version`
+    /// entry if that is higher. Unreadable versions count as 1.4.
+    pub fn of_document(document: &Document) -> PdfVersion {
+        let header = document.version.parse().unwrap_or(PdfVersion::V1_4);
+        let catalog = document
+            .catalog()
+            .and_then(|catalog| catalog.get(b"Version"))
+            .and_then(Object::as_name)
+            .ok()
+            .and_then(|version| std::str::from_utf8(version).ok())
+            .and_then(|version| version.parse().ok());
+        catalog.map_or(header, |catalog| header.max(catalog))
+    }
+}
+
+impl fmt::Display for PdfVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for PdfVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(version: &str) -> Result<Self> {
+        let version = version.trim();
+        match version.split_once('.') {
+            Some(("1", minor)) if matches!(minor, "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7") => {
+                Ok(PdfVersion {
+                    major: 1,
+                    minor: minor.parse()?,
+                })
+            }
+            Some(("2", "0")) => Ok(PdfVersion::V2_0),
+            _ => anyhow::bail!("'{}' is not a PDF version, expected 1.0 to 1.7 or 2.0", version),
+        }
+    }
+}
+
+/// Manifests may spell versions as strings or, unquoted, as numbers.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VersionValue {
+    Text(String),
+    Number(f64),
+}
+
+impl TryFrom<VersionValue> for PdfVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(version: VersionValue) -> Result<Self> {
+        match version {
+            VersionValue::Text(version) => version.parse(),
+            VersionValue::Number(version) => format!("{:.1}", version).parse(),
+        }
+    }
+}
+
+/// Features of an input that only exist from a certain PDF version on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PdfFeature {
+    ObjectStreams,
+    Aes128Encryption,
+    Aes256Encryption,
+}
+
+impl PdfFeature {
+    pub fn required_version(self) -> PdfVersion {
+        match self {
+            PdfFeature::ObjectStreams => PdfVersion::V1_5,
+            PdfFeature::Aes128Encryption => PdfVersion::V1_6,
+            PdfFeature::Aes256Encryption => PdfVersion::V2_0,
+        }
+    }
+
+    /// The features `document` was stored with. lopdf keeps the object streams it
+    /// unpacked, and encryption is detected from the state it keeps after decrypting.
+    pub fn of_document(document: &Document) -> Vec<PdfFeature> {
+        let mut features = Vec::new();
+        let has_object_streams = document
+            .objects
+            .values()
+            .any(|object| object.as_stream().is_ok_and(|stream| stream.dict.has_type(b"ObjStm")));
+        if has_object_streams {
+            features.push(PdfFeature::ObjectStreams);
+        }
+        if let Some(state) = &document.encryption_state {
+            match state.get_stream_filter().method() {
+                b"AESV2" => features.push(PdfFeature::Aes128Encryption),
+                b"AESV3" => features.push(PdfFeature::Aes256Encryption),
+                _ => {}
+            }
+        }
+        features
+    }
+}
+
+impl fmt::Display for PdfFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PdfFeature::ObjectStreams => write!(f, "object streams"),
+            PdfFeature::Aes128Encryption => write!(f, "AES-128 encryption"),
+            PdfFeature::Aes256Encryption => write!(f, "AES-256 encryption"),
+        }
+    }
+}
+
+/// The version and layout the output is written with, and what had to give way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputVersion {
+    pub version: PdfVersion,
+    pub layout: OutputLayout,
+    pub warnings: Vec<String>,
+}
+
+/// Picks the output version. Without a `requested` version this is the highest version
+/// of the inputs, raised to what the layout and the XMP metadata stream need. A requested
+/// version is used as is, with a warning for every feature it cannot express.
+pub fn select_output_version(
+    requested: Option<PdfVersion>,
+    layout: OutputLayout,
+    inputs: &[(&str, &MergableDocument)],
+) -> OutputVersion {
+    let Some(version) = requested else {
+        let minimum = match layout {
+            OutputLayout::ObjectStreams => PdfVersion::V1_5,
+            OutputLayout::Classic | OutputLayout::Linearized => PdfVersion::V1_4,
+        };
+        let version = inputs
+            .iter()
+            .map(|(_, document)| document.get_version())
+            .fold(minimum, PdfVersion::max);
+        return OutputVersion {
+            version,
+            layout,
+            warnings: Vec::new(),
+        };
+    };
+
+    let mut warnings = Vec::new();
+    for (file, document) in inputs {
+        for feature in document.get_features() {
+            if feature.required_version() > version {
+                warnings.push(format!(
+                    "{} uses {} (PDF {}), which PDF {} does not support; it is dropped from the output",
+                    file,
+                    feature,
+                    feature.required_version(),
+                    version
+                ));
+            }
+        }
+    }
+    let layout = if layout == OutputLayout::ObjectStreams && version < PdfVersion::V1_5 {
+        warnings.push(format!(
+            "Object streams need PDF {}, writing a classic cross-reference table for PDF {}",
+            PdfVersion::V1_5,
+            version
+        ));
+        OutputLayout::Classic
+    } else {
+        layout
+    };
+
+    OutputVersion {
+        version,
+        layout,
+        warnings,
+    }
+}
+
+/// Declares `version` in the header and drops a catalog `/Version` that would override it.
+pub fn set_output_version(document: &mut Document, version: PdfVersion) {
+    document.version = version.to_string();
+    if let Ok(catalog) = document.catalog_mut() {
+        catalog.remove(b"Version");
+    }
+}
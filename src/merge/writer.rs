@@ -0,0 +1,138 @@
+use std::io::{Result, Write};
+
+use lopdf::{Dictionary, Object, Stream, StringFormat};
+use serde::Serialize;
+
+/// Size of one entry in a classic cross-reference table.
+const XREF_ENTRY_SIZE: u64 = 20;
+
+// lopdf keeps its object writer private, so this mirrors its output byte for byte
+// wherever stapler needs to measure or lay out serialized objects itself.
+
+fn need_separator(object: &Object) -> bool {
+    matches!(
+        object,
+        Object::Null | Object::Boolean(_) | Object::Integer(_) | Object::Real(_) | Object::Reference(_)
+    )
+}
+
+fn write_name(out: &mut dyn Write, name: &[u8]) -> Result<()> {
+    out.write_all(b"/")?;
+    for &byte in name {
+        if b" \t\n\r\x0C()<>[]{}/%#".contains(&byte) || !(33..=126).contains(&byte) {
+            write!(out, "#{:02X}", byte)?;
+        } else {
+            out.write_all(&[byte])?;
+        }
+    }
+    Ok(())
+}
+
+fn write_string(out: &mut dyn Write, text: &[u8], format: &StringFormat) -> Result<()> {
+    match format {
+        StringFormat::Literal => {
+            out.write_all(b"(")?;
+            for &byte in text {
+                if matches!(byte, b'(' | b')' | b'\\' | b'\r') {
+                    out.write_all(b"\\")?;
+                }
+                out.write_all(&[byte])?;
+            }
+            out.write_all(b")")
+        }
+        StringFormat::Hexadecimal => {
+            out.write_all(b"<")?;
+            for byte in text {
+                write!(out, "{:02X}", byte)?;
+            }
+            out.write_all(b">")
+        }
+    }
+}
+
+fn write_array(out: &mut dyn Write, array: &[Object]) -> Result<()> {
+    out.write_all(b"[")?;
+    for (index, object) in array.iter().enumerate() {
+        if index > 0 && need_separator(object) {
+            out.write_all(b" ")?;
+        }
+        write_object(out, object)?;
+    }
+    out.write_all(b"]")
+}
+
+pub fn write_dictionary(out: &mut dyn Write, dictionary: &Dictionary) -> Result<()> {
+    out.write_all(b"<<")?;
+    for (key, value) in dictionary {
+        write_name(out, key)?;
+        if need_separator(value) {
+            out.write_all(b" ")?;
+        }
+        write_object(out, value)?;
+    }
+    out.write_all(b">>")
+}
+
+fn write_stream(out: &mut dyn Write, stream: &Stream) -> Result<()> {
+    write_dictionary(out, &stream.dict)?;
+    out.write_all(b"stream\n")?;
+    out.write_all(&stream.content)?;
+    out.write_all(b"\nendstream")
+}
+
+pub fn write_object(out: &mut dyn Write, object: &Object) -> Result<()> {
+    match object {
+        Object::Null => out.write_all(b"null"),
+        Object::Boolean(value) => out.write_all(if *value { b"true" } else { b"false" }),
+        Object::Integer(value) => write!(out, "{}", value),
+        Object::Real(value) => write!(out, "{}", value),
+        Object::Name(name) => write_name(out, name),
+        Object::String(text, format) => write_string(out, text, format),
+        Object::Array(array) => write_array(out, array),
+        Object::Dictionary(dictionary) => write_dictionary(out, dictionary),
+        Object::Stream(stream) => write_stream(out, stream),
+        Object::Reference(id) => write!(out, "{} {} R", id.0, id.1),
+    }
+}
+
+/// Writes a complete `N G obj ... endobj` block the way lopdf does when saving.
+pub fn write_indirect_object(out: &mut dyn Write, id: (u32, u16), object: &Object) -> Result<()> {
+    write!(out, "{} {} obj\n{}", id.0, id.1, if need_separator(object) { " " } else { "" })?;
+    write_object(out, object)?;
+    let end_separator = need_separator(object) || matches!(object, Object::Name(_) | Object::Stream(_));
+    writeln!(out, "{}\nendobj", if end_separator { " " } else { "" })
+}
+
+/// Number of bytes the object occupies in a saved file, including its `obj`/`endobj` frame.
+pub fn serialized_size(id: (u32, u16), object: &Object) -> u64 {
+    let mut counter = ByteCounter(0);
+    let _ = write_indirect_object(&mut counter, id, object);
+    counter.0
+}
+
+/// Objects dropped from a document and the bytes they would have taken up in the output.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RemovalSummary {
+    pub objects_removed: usize,
+    pub bytes_saved: u64,
+}
+
+impl RemovalSummary {
+    pub fn record(&mut self, id: (u32, u16), object: &Object) {
+        self.objects_removed += 1;
+        self.bytes_saved += serialized_size(id, object) + XREF_ENTRY_SIZE;
+    }
+}
+
+struct ByteCounter(u64);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::merge::images::ImageSummary;
+use crate::merge::writer::RemovalSummary;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InputReport {
+    pub file: String,
+    /// First and last page of the output showing pages of the input. With imposition
+    /// these are sheets, which a booklet may share with other inputs.
+    pub first_page: usize,
+    pub last_page: usize,
+    /// Number of pages taken from the input.
+    pub page_count: usize,
+    pub object_count: usize,
+    /// Page numbers in the input file of the blank pages left out.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed_blank_pages: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TimingReport {
+    pub load_ms: u64,
+    pub merge_ms: u64,
+    pub save_ms: u64,
+    pub total_ms: u64,
+}
+
+impl TimingReport {
+    pub fn from_durations(load: Duration, merge: Duration, save: Duration) -> Self {
+        TimingReport {
+            load_ms: load.as_millis() as u64,
+            merge_ms: merge.as_millis() as u64,
+            save_ms: save.as_millis() as u64,
+            total_ms: (load + merge + save).as_millis() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MergeReport {
+    pub output_file: String,
+    pub inputs: Vec<InputReport>,
+    /// Number of pages of the output, sheets when pages are imposed.
+    pub page_count: usize,
+    pub object_count: usize,
+    pub output_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deduplication: Option<RemovalSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pruning: Option<RemovalSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub images: Option<ImageSummary>,
+    pub warnings: Vec<String>,
+    pub timings: TimingReport,
+}
+
+impl MergeReport {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JobReport {
+    pub output_file: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<MergeReport>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BatchReport {
+    pub jobs: Vec<JobReport>,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub total_ms: u64,
+}
+
+impl BatchReport {
+    pub fn has_failures(&self) -> bool {
+        self.failed > 0
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
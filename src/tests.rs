@@ -0,0 +1,277 @@
+use std::{env::temp_dir, fs, path::PathBuf};
+
+use lopdf::{dictionary, Object, Stream};
+
+use super::*;
+use crate::merge::blank::BlankPageOptions;
+use crate::merge::imposition::Imposition;
+use crate::merge::loader::fs::{FileSystemMergingDestination, FileSystemMergingSource};
+use crate::merge::merge_documents;
+use crate::merge::tests::create_sample_pdf;
+
+/// A sample document whose font is embedded, so it conforms to PDF/A-2b.
+fn create_embedded_pdf(title: &str) -> Document {
+    let mut doc = create_sample_pdf(title);
+    let font_file = doc.add_object(Stream::new(dictionary! {}, b"font program".to_vec()));
+    let descriptor = doc.add_object(dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => "Courier",
+        "FontFile" => font_file,
+    });
+    for object in doc.objects.values_mut() {
+        if let Ok(font) = object.as_dict_mut() {
+            if font.has_type(b"Font") {
+                font.set("FontDescriptor", descriptor);
+            }
+        }
+    }
+    doc
+}
+
+/// Saves `documents` into a fresh directory and returns options merging them, in order.
+fn options_for(test: &str, documents: Vec<(&str, Document)>) -> (PathBuf, StaplerOptions) {
+    let directory = temp_dir().join(format!("stapler-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let input_sources = documents
+        .into_iter()
+        .map(|(name, mut document)| {
+            let path = directory.join(name);
+            document.save(&path).unwrap();
+            FileSystemMergingSource::new(&path.to_string_lossy())
+        })
+        .collect();
+    let options = StaplerOptions {
+        input_sources,
+        destination: FileSystemMergingDestination {
+            output_file: directory.join("merged.pdf").to_string_lossy().to_string(),
+        },
+        ..Default::default()
+    };
+    (directory, options)
+}
+
+#[test]
+fn test_pdfa_violations_name_the_input_when_imposing() {
+    let (directory, mut options) = options_for(
+        "pdfa-imposition",
+        vec![
+            ("first.pdf", create_embedded_pdf("First")),
+            ("second.pdf", create_embedded_pdf("Second")),
+            ("plain.pdf", create_sample_pdf("Plain")),
+        ],
+    );
+    options.pdfa = true;
+    options.imposition = Some(Imposition::Grid { columns: 2, rows: 1 });
+
+    let error = stapler(options).unwrap_err().to_string();
+
+    // The offending page ends up on sheet 2, next to nothing from second.pdf.
+    let plain = directory.join("plain.pdf").to_string_lossy().to_string();
+    assert_eq!(
+        error,
+        format!(
+            "The merged document cannot conform to PDF/A-2b:\n  page 3 ({} page 1): font Courier is not embedded",
+            plain
+        )
+    );
+    fs::remove_dir_all(directory).unwrap();
+}
+
+fn create_blank_pdf() -> Document {
+    let mut doc = create_sample_pdf("Blank");
+    let page_id = doc.page_iter().next().unwrap();
+    let empty = doc.add_object(Stream::new(dictionary! {}, Vec::new()));
+    doc.get_dictionary_mut(page_id).unwrap().set("Contents", empty);
+    doc
+}
+
+#[test]
+fn test_blank_inputs_are_left_out() {
+    let (directory, mut options) = options_for(
+        "blank-inputs",
+        vec![("blank.pdf", create_blank_pdf()), ("text.pdf", create_sample_pdf("Text"))],
+    );
+    options.blank_pages = Some(BlankPageOptions::default());
+
+    let report = stapler(options.clone()).unwrap();
+
+    assert_eq!(report.page_count, 1);
+    assert_eq!(report.inputs[0].page_count, 0);
+    assert_eq!(report.inputs[0].removed_blank_pages, [1]);
+    assert_eq!((report.inputs[1].first_page, report.inputs[1].last_page), (1, 1));
+    let merged = Document::load(&options.destination.output_file).unwrap();
+    assert_eq!(merged.get_pages().len(), 1);
+
+    options.input_sources.remove(1);
+    options.input_sources.push(options.input_sources[0].clone());
+    let error = stapler(options).unwrap_err();
+    assert_eq!(error.to_string(), "Every page of every input is blank");
+    fs::remove_dir_all(directory).unwrap();
+}
+
+/// A document with a page per title.
+fn create_pages_pdf(titles: &[&str]) -> Document {
+    let documents = titles
+        .iter()
+        .map(|title| MergableDocument::from_document(&format!("{}.pdf", title), create_sample_pdf(title)))
+        .collect();
+    merge_documents(documents).unwrap()
+}
+
+#[test]
+fn test_report_describes_each_input() {
+    let (directory, options) = options_for(
+        "report",
+        vec![
+            ("two.pdf", create_pages_pdf(&["A", "B"])),
+            ("three.pdf", create_pages_pdf(&["C", "D", "E"])),
+        ],
+    );
+
+    let report = stapler(options.clone()).unwrap();
+
+    let ranges = report
+        .inputs
+        .iter()
+        .map(|input| (input.first_page, input.last_page, input.page_count))
+        .collect::<Vec<_>>();
+    assert_eq!(ranges, [(1, 2, 2), (3, 5, 3)]);
+    assert_eq!(report.inputs[1].file, options.input_sources[1].input_file);
+    assert_eq!(report.page_count, 5);
+    let output_size = fs::metadata(&options.destination.output_file).unwrap().len();
+    assert_eq!(report.output_size, output_size);
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+    let keys = |value: &serde_json::Value| value.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+    assert_eq!(
+        keys(&json),
+        ["inputs", "object_count", "output_file", "output_size", "page_count", "timings", "warnings"]
+    );
+    assert_eq!(
+        keys(&json["inputs"][0]),
+        ["file", "first_page", "last_page", "object_count", "page_count"]
+    );
+    assert_eq!(json["inputs"][1]["first_page"], 3);
+    assert_eq!(keys(&json["timings"]), ["load_ms", "merge_ms", "save_ms", "total_ms"]);
+
+    // Imposed, the spans are the sheets the pages land on: 1-2, 3-4 and 5 share sheets.
+    let mut imposed = options.clone();
+    imposed.imposition = Some(Imposition::Grid { columns: 2, rows: 1 });
+    let report = stapler(imposed).unwrap();
+    let ranges = report
+        .inputs
+        .iter()
+        .map(|input| (input.first_page, input.last_page, input.page_count))
+        .collect::<Vec<_>>();
+    assert_eq!(ranges, [(1, 1, 2), (2, 3, 3)]);
+    assert_eq!(report.page_count, 3);
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_parallel_loading_keeps_input_order() {
+    let titles = ["Alpha", "Bravo", "Charlie", "Delta", "Echo", "Foxtrot"];
+    let names = titles.map(|title| format!("{}.pdf", title.to_lowercase()));
+    let (directory, mut options) = options_for(
+        "parallel-loading",
+        names
+            .iter()
+            .zip(titles)
+            .map(|(name, title)| (name.as_str(), create_pages_pdf(&[title, title])))
+            .collect(),
+    );
+    for (source, title) in options.input_sources.iter_mut().zip(titles) {
+        source.bookmark = Some(format!("Chapter {}", title));
+    }
+    options.jobs = Some(4);
+
+    stapler(options.clone()).unwrap();
+
+    let merged = Document::load(&options.destination.output_file).unwrap();
+    let pages = merged
+        .page_iter()
+        .map(|page_id| String::from_utf8(merged.get_page_content(page_id).unwrap()).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(pages.len(), 2 * titles.len());
+    for (page, title) in pages.iter().zip(titles.iter().flat_map(|title| [title, title])) {
+        assert!(page.contains(&format!("({})", title)), "{} is not on {}", title, page);
+    }
+    let outline = merged
+        .get_toc()
+        .unwrap()
+        .toc
+        .into_iter()
+        .map(|entry| (entry.title, entry.page))
+        .collect::<Vec<_>>();
+    let expected = titles
+        .iter()
+        .enumerate()
+        .map(|(index, title)| (format!("Chapter {}", title), 2 * index + 1))
+        .collect::<Vec<_>>();
+    assert_eq!(outline, expected);
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_imposition_keeps_bookmarks_and_named_destinations() {
+    let mut first = create_sample_pdf("First");
+    let page_id = first.page_iter().next().unwrap();
+    first
+        .catalog_mut()
+        .unwrap()
+        .set("Dests", dictionary! { "intro" => vec![page_id.into(), "Fit".into()] });
+    let (directory, mut options) = options_for(
+        "imposition-bookmarks",
+        vec![
+            ("first.pdf", first),
+            ("second.pdf", create_sample_pdf("Second")),
+            ("third.pdf", create_sample_pdf("Third")),
+        ],
+    );
+    for (index, source) in options.input_sources.iter_mut().enumerate() {
+        source.bookmark = Some(format!("Chapter {}", index + 1));
+    }
+    options.imposition = Some(Imposition::Grid { columns: 2, rows: 1 });
+
+    stapler(options.clone()).unwrap();
+
+    let merged = Document::load(&options.destination.output_file).unwrap();
+    let sheets = merged.get_pages().into_values().collect::<Vec<_>>();
+    assert_eq!(sheets.len(), 2);
+    let outline = merged
+        .get_toc()
+        .unwrap()
+        .toc
+        .into_iter()
+        .map(|entry| (entry.title, entry.page))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        outline,
+        [("Chapter 1".to_string(), 1), ("Chapter 2".to_string(), 1), ("Chapter 3".to_string(), 2)]
+    );
+    let dests = merged.catalog().unwrap().get(b"Dests").unwrap();
+    let dests = merged.dereference(dests).unwrap().1.as_dict().unwrap();
+    let intro = dests.get(b"intro").and_then(Object::as_array).unwrap();
+    assert_eq!(intro[0].as_reference().unwrap(), sheets[0]);
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_metadata_is_only_written_when_asked_for() {
+    let (directory, mut options) = options_for(
+        "metadata",
+        vec![("a.pdf", create_sample_pdf("A")), ("b.pdf", create_sample_pdf("B"))],
+    );
+
+    stapler(options.clone()).unwrap();
+    let merged = Document::load(&options.destination.output_file).unwrap();
+    assert!(merged.trailer.get(b"Info").is_err());
+    assert!(merged.catalog().unwrap().get(b"Metadata").is_err());
+
+    options.metadata.title = Some("Bundle".to_string());
+    stapler(options.clone()).unwrap();
+    let merged = Document::load(&options.destination.output_file).unwrap();
+    assert!(merged.trailer.get(b"Info").is_ok());
+    assert!(merged.catalog().unwrap().get(b"Metadata").is_ok());
+    fs::remove_dir_all(directory).unwrap();
+}